@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+
+use huffman::{histogram, HuffmanEncoder};
+
+const SIZE: usize = 1 << 20;
+
+fn text_input() -> Vec<u8> {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(SIZE)
+        .map(|c| c as u8)
+        .collect()
+}
+
+/// The per byte HashMap counting the encoder used before histogram()
+fn hashmap_count(data: &[u8]) -> HashMap<u8, u64> {
+    let mut counts = HashMap::new();
+    for c in data {
+        *counts.entry(*c).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn bench_histogram(c: &mut Criterion) {
+    let inputs = vec![
+        ("text", text_input()),
+        ("zeros", vec![0u8; SIZE]),
+    ];
+
+    let mut group = c.benchmark_group("histogram");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+
+    for (name, input) in &inputs {
+        group.bench_function(format!("interleaved/{}", name), |b| {
+            b.iter(|| histogram(black_box(input)))
+        });
+        group.bench_function(format!("hashmap/{}", name), |b| {
+            b.iter(|| hashmap_count(black_box(input)))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_add_chunk(c: &mut Criterion) {
+    let input = text_input();
+
+    let mut group = c.benchmark_group("add_chunk");
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    group.bench_function("text", |b| {
+        b.iter(|| {
+            let mut h = HuffmanEncoder::new();
+            h.add_chunk(black_box(&input));
+            h
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_histogram, bench_add_chunk);
+criterion_main!(benches);
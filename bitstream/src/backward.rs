@@ -0,0 +1,151 @@
+/// Writes bits the way zstd and other FSE based formats do: each value is
+/// placed above the bits written before it, least significant bit first, and
+/// the bytes are little endian. The stream is closed with a single 1 bit so
+/// that a BackwardBitReader can find where it ends and read it back to front.
+pub struct BackwardBitWriter {
+    buffer: Vec<u8>,
+    bits: u64,
+    bit_count: u32,
+}
+
+impl BackwardBitWriter {
+    /// Create a new, empty BackwardBitWriter.
+    pub fn new() -> BackwardBitWriter {
+        BackwardBitWriter {
+            buffer: Vec::new(),
+            bits: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Add the lowest count bits of val. count must not be larger than 56.
+    pub fn add_bits(&mut self, val: u64, count: u8) {
+        let mask = (1u64 << count) - 1;
+        self.bits |= (val & mask) << self.bit_count;
+        self.bit_count += count as u32;
+
+        while self.bit_count >= 8 {
+            self.buffer.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// Returns the number of bits written so far.
+    pub fn bit_len(&self) -> usize {
+        self.buffer.len() * 8 + self.bit_count as usize
+    }
+
+    /// Adds the closing 1 bit, pads the last byte with 0 bits and returns
+    /// the written bytes.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.add_bits(1, 1);
+        if self.bit_count > 0 {
+            self.buffer.push(self.bits as u8);
+        }
+        self.buffer
+    }
+}
+
+impl Default for BackwardBitWriter {
+    fn default() -> BackwardBitWriter {
+        BackwardBitWriter::new()
+    }
+}
+
+/// Reads a stream written by a BackwardBitWriter from its last bit to its
+/// first, so values come out in the reverse order they were written. Reading
+/// past the start of the stream returns 0 bits, which some formats rely on to
+/// detect the end, and is reported by overflowed().
+pub struct BackwardBitReader<'a> {
+    data: &'a [u8],
+    /// the number of bits left to read, negative once reading went past the
+    /// start of the stream
+    remaining: i64,
+}
+
+impl<'a> BackwardBitReader<'a> {
+    /// Create a new BackwardBitReader positioned below the closing 1 bit.
+    /// Returns None if data is empty or its last byte is 0, in which case
+    /// the closing bit is missing.
+    pub fn new(data: &'a [u8]) -> Option<BackwardBitReader<'a>> {
+        let last = *data.last()?;
+        if last == 0 {
+            return None;
+        }
+
+        let below_marker = 7 - last.leading_zeros() as i64;
+        Some(BackwardBitReader {
+            data,
+            remaining: (data.len() as i64 - 1) * 8 + below_marker,
+        })
+    }
+
+    /// Returns the next count bits without consuming them, the first bit
+    /// being the most significant. count must not be larger than 56.
+    pub fn peek_bits(&self, count: u8) -> u64 {
+        let mut val = 0;
+        for i in 1..=count as i64 {
+            let pos = self.remaining - i;
+            let bit = if pos >= 0 {
+                (self.data[pos as usize / 8] >> (pos % 8)) & 1
+            } else {
+                0
+            };
+            val = (val << 1) | bit as u64;
+        }
+        val
+    }
+
+    /// Consumes count bits.
+    pub fn skip_bits(&mut self, count: u8) {
+        self.remaining -= count as i64;
+    }
+
+    /// Reads the next count bits, the first bit being the most significant.
+    /// count must not be larger than 56.
+    pub fn read_bits(&mut self, count: u8) -> u64 {
+        let val = self.peek_bits(count);
+        self.skip_bits(count);
+        val
+    }
+
+    /// Returns the number of bits left before the start of the stream.
+    pub fn bits_remaining(&self) -> usize {
+        std::cmp::max(0, self.remaining) as usize
+    }
+
+    /// Returns true once more bits have been read than the stream holds.
+    pub fn overflowed(&self) -> bool {
+        self.remaining < 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backward_bits_roundtrip() {
+        let mut writer = BackwardBitWriter::new();
+        writer.add_bits(0b101, 3);
+        writer.add_bits(0x1234, 16);
+        writer.add_bits(0, 2);
+        assert_eq!(writer.bit_len(), 21);
+        let data = writer.finish();
+        assert_eq!(data, vec![0xa5, 0x91, 0x20]);
+
+        let mut reader = BackwardBitReader::new(&data).unwrap();
+        assert_eq!(reader.bits_remaining(), 21);
+        assert_eq!(reader.read_bits(2), 0);
+        assert_eq!(reader.peek_bits(4), 0x1);
+        assert_eq!(reader.read_bits(16), 0x1234);
+        assert_eq!(reader.read_bits(3), 0b101);
+        assert!(!reader.overflowed());
+        assert_eq!(reader.read_bits(4), 0);
+        assert!(reader.overflowed());
+
+        assert!(BackwardBitReader::new(&[0x12, 0x00]).is_none());
+        assert!(BackwardBitReader::new(&[]).is_none());
+    }
+}
@@ -2,62 +2,85 @@ use std::io::{Read,Result};
 use std::option::Option;
 use std::vec::Vec;
 
+/// A source of bits, read most significant bit first.
+pub trait ReadBits {
+    /// Returns the next bit, or None if there are no bits left.
+    fn get_bit(&mut self) -> Option<bool>;
+
+    /// Returns the next 8 bits as a byte, or None if there are not enough
+    /// bits left.
+    fn get_byte(&mut self) -> Option<u8> {
+        let mut ret: u8 = 0;
+
+        for i in (0..8).rev() {
+            ret |= (self.get_bit()? as u8) << i;
+        }
+
+        Some(ret)
+    }
+}
+
 pub struct BitReader<'a> {
     reader: &'a mut (dyn Read + 'a),
     buffer: Vec<u8>,
+    buffer_pos: usize,
     read_amount: usize,
     cur_byte_loc: u8,
 }
 
 impl<'a> BitReader<'a> {
-    pub fn with_reader(reader: &'a mut dyn Read) -> BitReader {
+    pub fn with_reader(reader: &'a mut dyn Read) -> BitReader<'a> {
         BitReader {
             reader: reader,
             buffer: Vec::new(),
+            buffer_pos: 0,
             read_amount: 128,
             cur_byte_loc: 0,
         }
     }
 
     pub fn get_bit(&mut self) -> Option<bool> {
-        if self.buffer.len() == 0 && self.cur_byte_loc == 0 {
+        if self.buffer_pos == self.buffer.len() && self.cur_byte_loc == 0 {
             self.buffer.resize(self.read_amount, 0);
             let size = self.reader.read(&mut self.buffer).unwrap();
             if size == 0 {
+                self.buffer.clear();
+                self.buffer_pos = 0;
                 return None;
             }
-            self.buffer.resize(size, 0);
+            self.buffer.truncate(size);
+            self.buffer_pos = 0;
         }
 
         let mask = (1 as u8) << (7 - self.cur_byte_loc);
-        let val = self.buffer[0] & mask;
+        let val = self.buffer[self.buffer_pos] & mask;
         
         self.cur_byte_loc += 1;
 
         if self.cur_byte_loc == 8 {
             self.cur_byte_loc = 0;
-            self.buffer = self.buffer.split_off(1);
+            self.buffer_pos += 1;
         }
 
         Some(val != 0)
     }
 
     pub fn get_byte(&mut self) -> Option<u8> {
-        let mut ret: u8 = 0;
-
-        for i in (0..8).rev() {
-            ret |= (self.get_bit()? as u8) << i;
-        }
-
-        Some(ret)
+        ReadBits::get_byte(self)
     }
 
     pub fn set_read_amount(&mut self, val: usize) {
         self.read_amount = val;
     }
 
-    pub fn into_remaining(self) -> Vec<u8> {
-        self.buffer
+    pub fn into_remaining(mut self) -> Vec<u8> {
+        self.buffer.split_off(self.buffer_pos)
+    }
+}
+
+impl<'a> ReadBits for BitReader<'a> {
+    fn get_bit(&mut self) -> Option<bool> {
+        BitReader::get_bit(self)
     }
 }
 
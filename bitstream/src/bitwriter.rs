@@ -29,7 +29,7 @@ pub struct BitWriter<'a>
 impl<'a> BitWriter<'a> {
     /// Create a new BitWriter struct with the given writer as the endpoint of the BitWriter.
     /// The lifetime of the passed writer must exceed the lifetime of the BitWriter itsself.
-    pub fn with_writer(writer: &'a mut dyn Write) -> BitWriter {
+    pub fn with_writer(writer: &'a mut dyn Write) -> BitWriter<'a> {
         BitWriter {
             writer: writer,
             buffer: Vec::with_capacity(128),
@@ -69,6 +69,16 @@ impl<'a> BitWriter<'a> {
         Ok(())
     }
 
+    /// Add the lowest count bits of val to the BitWriter, starting with the most
+    /// significant of them. count must not be larger than 64.
+    pub fn add_bits(&mut self, val: u64, count: u8) -> Result<()> {
+        for i in (0..count).rev() {
+            self.add_bit((val >> i) & 1 != 0)?;
+        }
+
+        Ok(())
+    }
+
     /// Sets the write threshold on the BitWriter to be val bytes. When the write threshold
     /// has been reached on the internal buffer the BitWriter will call write on the 
     /// underlying Writer.
@@ -135,6 +145,20 @@ mod tests {
         assert_eq!(v[0], 0b10110000);
     }
 
+    #[test]
+    fn bit_writer_add_bits() {
+        let mut vs = VecStream::new();
+        let mut bt = BitWriter::with_writer(&mut vs);
+
+        bt.add_bits(0b101, 3).unwrap();
+        bt.add_bits(0x1ff, 9).unwrap();
+        bt.add_bits(u64::MAX, 0).unwrap();
+        drop(bt);
+
+        let v = vs.into_vec();
+        assert_eq!(v, vec![0b10111111, 0b11110000]);
+    }
+
     #[test]
     fn bit_writer_multibyte() {
         let bit_arr: [u8; 16] = [1,0,0,1,0,0,1,0,0,0,1,1,0,0,1,0];
@@ -1,9 +1,13 @@
+mod backward;
 mod bitreader;
 mod bitwriter;
+mod slicereader;
 mod vecstream;
 
 
+pub use backward::{BackwardBitReader, BackwardBitWriter};
 pub use bitwriter::BitWriter;
-pub use bitreader::BitReader;
+pub use bitreader::{BitReader, ReadBits};
+pub use slicereader::SliceBitReader;
 pub use vecstream::VecStream;
 
@@ -0,0 +1,68 @@
+use crate::bitreader::ReadBits;
+
+/// Reads bits directly out of a borrowed slice without copying or buffering
+/// any of it. Bits are read most significant bit first, like the BitReader.
+pub struct SliceBitReader<'a> {
+    data: &'a [u8],
+    bit_pos: usize,
+}
+
+impl<'a> SliceBitReader<'a> {
+    /// Create a new SliceBitReader positioned at the first bit of data.
+    pub fn new(data: &'a [u8]) -> SliceBitReader<'a> {
+        SliceBitReader {
+            data,
+            bit_pos: 0,
+        }
+    }
+
+    /// Returns the number of bits read so far.
+    pub fn bit_position(&self) -> usize {
+        self.bit_pos
+    }
+
+    /// Moves the reader to the given bit of the data. Positions past the end
+    /// of the data are allowed, no bits can be read from them.
+    pub fn set_bit_position(&mut self, bit_pos: usize) {
+        self.bit_pos = bit_pos;
+    }
+
+    /// Returns the bytes that have not been started yet. A partially read
+    /// byte is not included.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.bit_pos.div_ceil(8)..]
+    }
+}
+
+impl<'a> ReadBits for SliceBitReader<'a> {
+    fn get_bit(&mut self) -> Option<bool> {
+        let byte = *self.data.get(self.bit_pos / 8)?;
+        let val = byte & (0x80 >> (self.bit_pos % 8));
+        self.bit_pos += 1;
+        Some(val != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slice_bit_reader_basic() {
+        let data = [0b10010110, 0xff];
+        let mut sr = SliceBitReader::new(&data);
+
+        let bits: Vec<bool> = (0..4).map(|_| sr.get_bit().unwrap()).collect();
+        assert_eq!(bits, vec![true, false, false, true]);
+        assert_eq!(sr.bit_position(), 4);
+        assert_eq!(sr.remaining(), &[0xff]);
+
+        assert_eq!(sr.get_byte(), Some(0b01101111));
+        assert_eq!(sr.get_byte(), None);
+
+        sr.set_bit_position(6);
+        assert_eq!(sr.get_byte(), Some(0b10111111));
+        sr.set_bit_position(100);
+        assert_eq!(sr.get_bit(), None);
+    }
+}
@@ -0,0 +1,350 @@
+//! A bzip2 style pipeline that goes in front of the HuffmanEncoder: the
+//! Burrows-Wheeler transform, move-to-front and run-length coding of zeros.
+//!
+//! The Burrows-Wheeler transform sorts all rotations of a block and keeps the
+//! last byte of each, which groups bytes that appear in the same context.
+//! Move-to-front turns those groups into runs of small values, mostly zeros,
+//! and the runs of zeros are coded by their length. Byte-level huffman coding
+//! of the result compresses text far better than of the text itself.
+//!
+//! The transformed data is a sequence of blocks, each holding:
+//!
+//! * `u32` little endian: size of the original block
+//! * `u32` little endian: position of the original block among its sorted
+//!   rotations
+//! * `u32` little endian: size of the coded block
+//! * the coded block
+//!
+//! In the coded block runs of zeros are written in bijective base 2 with the
+//! digits 0 and 1, least significant first, as bzip2 writes them with its
+//! RUNA and RUNB symbols. Other values v up to 253 are written as v + 1, and
+//! 254 and 255 as 255 followed by v - 254.
+
+use crate::error::{Error, Result};
+use crate::huffman_decoder::{DecodeLimits, HuffmanDecoder};
+use crate::huffman_encoder::HuffmanEncoder;
+
+/// The size of the blocks the data is split into, the block size of
+/// bzip2 -9.
+pub const BLOCK_SIZE: usize = 900_000;
+
+/// The size of the fields in front of every block.
+const BLOCK_HEADER_LEN: usize = 12;
+
+/// The largest ratio of original to compressed size: a block of BLOCK_SIZE
+/// bytes transforms to its fields and at least one coded byte, and every
+/// transformed byte takes at least one bit.
+const MAX_RATIO: u64 = BLOCK_SIZE as u64 * 8 / (BLOCK_HEADER_LEN as u64 + 1);
+
+/// Transforms data and huffman codes the result.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = HuffmanEncoder::new();
+    encoder.add_chunk(&transform(data));
+    encoder.encode()
+}
+
+/// Decodes data produced by compress(), with a ratio limit that accepts
+/// everything compress() produces and no limit on the output size.
+pub fn decompress(buf: &[u8]) -> Result<Vec<u8>> {
+    let limits = DecodeLimits { max_ratio: MAX_RATIO, ..DecodeLimits::default() };
+    decompress_with_limits(buf, &limits)
+}
+
+/// Decodes data produced by compress(), failing with Error::LimitExceeded if
+/// the huffman coded data or the original data would exceed any of the
+/// limits. The ratio limit applies to the size of buf.
+pub fn decompress_with_limits(buf: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>> {
+    let transformed = HuffmanDecoder::decode_with_limits(buf, limits)?;
+    inverse_transform_with_limits(&transformed, limits, buf.len())
+}
+
+/// Applies the Burrows-Wheeler transform, move-to-front and the coding of
+/// zero runs to every block of data.
+pub fn transform(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for block in data.chunks(BLOCK_SIZE) {
+        let (last_column, primary) = bwt(block);
+        let coded = encode_zero_runs(&move_to_front(&last_column));
+
+        out.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(primary as u32).to_le_bytes());
+        out.extend_from_slice(&(coded.len() as u32).to_le_bytes());
+        out.extend_from_slice(&coded);
+    }
+    out
+}
+
+/// Reverses transform().
+pub fn inverse_transform(buf: &[u8]) -> Result<Vec<u8>> {
+    inverse_transform_with_limits(buf, &DecodeLimits::unlimited(), buf.len())
+}
+
+/// Reverses transform(), checking the size of the original data before
+/// every block against the output and ratio limits for data compressed to
+/// encoded_len bytes.
+fn inverse_transform_with_limits(mut buf: &[u8], limits: &DecodeLimits, encoded_len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    while !buf.is_empty() {
+        let header = buf.get(..BLOCK_HEADER_LEN).ok_or(Error::UnexpectedEof)?;
+        let field = |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]) as usize;
+        let (len, primary, coded_len) = (field(0), field(4), field(8));
+
+        if len == 0 || len > BLOCK_SIZE || primary >= len {
+            return Err(Error::InvalidHeader("invalid block header"));
+        }
+        limits.check_output((out.len() + len) as u64, encoded_len)?;
+
+        let coded = buf.get(BLOCK_HEADER_LEN..BLOCK_HEADER_LEN + coded_len).ok_or(Error::UnexpectedEof)?;
+        let indexes = decode_zero_runs(coded, len)?;
+        if indexes.len() != len {
+            return Err(Error::InvalidCode("block size does not match its data"));
+        }
+        out.extend(inverse_bwt(&inverse_move_to_front(&indexes), primary)?);
+        buf = &buf[BLOCK_HEADER_LEN + coded_len..];
+    }
+    Ok(out)
+}
+
+/// Sorts all rotations of data and returns the last byte of every rotation
+/// in sorted order, along with the position of data itself among the
+/// rotations. Equal rotations keep the order of their starting positions.
+pub fn bwt(data: &[u8]) -> (Vec<u8>, usize) {
+    let n = data.len();
+    if n == 0 {
+        return (Vec::new(), 0);
+    }
+
+    // sort the rotations by prefix doubling: after a round with step k the
+    // rotations are sorted and ranked by their first 2k bytes
+    let mut order: Vec<usize> = (0..n).collect();
+    let mut rank: Vec<usize> = data.iter().map(|b| *b as usize).collect();
+    let mut next_rank = vec![0; n];
+    order.sort_by_key(|i| rank[*i]);
+    let mut k = 1;
+    while k < n {
+        let key = |i: usize| (rank[i], rank[(i + k) % n]);
+        order.sort_by_key(|i| key(*i));
+
+        next_rank[order[0]] = 0;
+        for w in 1..n {
+            let bump = (key(order[w - 1]) != key(order[w])) as usize;
+            next_rank[order[w]] = next_rank[order[w - 1]] + bump;
+        }
+        std::mem::swap(&mut rank, &mut next_rank);
+        if rank[order[n - 1]] == n - 1 {
+            break;
+        }
+        k *= 2;
+    }
+
+    let last_column = order.iter().map(|i| data[(i + n - 1) % n]).collect();
+    let primary = order.iter().position(|i| *i == 0).unwrap();
+    (last_column, primary)
+}
+
+/// Reverses bwt(). Fails with Error::InvalidHeader if primary is not a
+/// position in last_column.
+pub fn inverse_bwt(last_column: &[u8], primary: usize) -> Result<Vec<u8>> {
+    let n = last_column.len();
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+    if primary >= n {
+        return Err(Error::InvalidHeader("primary index out of range"));
+    }
+
+    // the rotation that ends with each byte of the last column, moved one
+    // byte to the right, is the row at the start of the byte's range in
+    // the first column plus the number of equal bytes before it
+    let mut starts = [0usize; 256];
+    for b in last_column {
+        starts[*b as usize] += 1;
+    }
+    let mut total = 0;
+    for start in starts.iter_mut() {
+        let count = *start;
+        *start = total;
+        total += count;
+    }
+    let previous_row: Vec<usize> = last_column.iter().map(|b| {
+        let row = starts[*b as usize];
+        starts[*b as usize] += 1;
+        row
+    }).collect();
+
+    let mut out = vec![0; n];
+    let mut row = primary;
+    for slot in out.iter_mut().rev() {
+        *slot = last_column[row];
+        row = previous_row[row];
+    }
+    Ok(out)
+}
+
+/// Replaces every byte with its position in a list of all byte values, and
+/// moves it to the front of the list.
+pub fn move_to_front(data: &[u8]) -> Vec<u8> {
+    let mut list: Vec<u8> = (0..=255).collect();
+    data.iter().map(|b| {
+        let pos = list.iter().position(|v| v == b).unwrap();
+        list[..=pos].rotate_right(1);
+        pos as u8
+    }).collect()
+}
+
+/// Reverses move_to_front().
+pub fn inverse_move_to_front(indexes: &[u8]) -> Vec<u8> {
+    let mut list: Vec<u8> = (0..=255).collect();
+    indexes.iter().map(|i| {
+        list[..=*i as usize].rotate_right(1);
+        list[0]
+    }).collect()
+}
+
+/// Codes runs of zeros by their length, see the module documentation.
+pub fn encode_zero_runs(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut run = 0;
+    for value in data {
+        if *value == 0 {
+            run += 1;
+            continue;
+        }
+
+        push_run(&mut out, run);
+        run = 0;
+        match value {
+            1..=253 => out.push(value + 1),
+            _ => out.extend_from_slice(&[255, value - 254]),
+        }
+    }
+    push_run(&mut out, run);
+    out
+}
+
+/// Writes the length of a run of zeros in bijective base 2, where the digit
+/// 0 is worth once and the digit 1 twice its place value.
+fn push_run(out: &mut Vec<u8>, mut run: usize) {
+    while run > 0 {
+        run -= 1;
+        out.push((run & 1) as u8);
+        run >>= 1;
+    }
+}
+
+/// Reverses encode_zero_runs(), producing at most max_len values. Fails
+/// with Error::InvalidCode if the coded data is malformed or longer.
+pub fn decode_zero_runs(coded: &[u8], max_len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut run: usize = 0;
+    let mut place: usize = 1;
+    let mut values = coded.iter();
+    while let Some(value) = values.next() {
+        if *value <= 1 {
+            run = place.checked_mul(*value as usize + 1)
+                .and_then(|r| r.checked_add(run))
+                .filter(|r| out.len() + r <= max_len)
+                .ok_or(Error::InvalidCode("zero run too long"))?;
+            place = place.saturating_mul(2);
+            continue;
+        }
+
+        out.resize(out.len() + run, 0);
+        run = 0;
+        place = 1;
+        match value {
+            255 => match values.next() {
+                Some(extra) if *extra <= 1 => out.push(254 + extra),
+                Some(_) => return Err(Error::InvalidCode("invalid escaped value")),
+                None => return Err(Error::UnexpectedEof),
+            },
+            _ => out.push(value - 1),
+        }
+        if out.len() > max_len {
+            return Err(Error::InvalidCode("too many values"));
+        }
+    }
+    out.resize(out.len() + run, 0);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bwt() {
+        let (last_column, primary) = bwt(b"banana");
+        assert_eq!((&last_column[..], primary), (&b"nnbaaa"[..], 3));
+        assert_eq!(inverse_bwt(&last_column, primary).unwrap(), b"banana");
+
+        for data in &[&b"a"[..], b"aaaa", b"abab", b"abcabcabc", b"mississippi"] {
+            let (last_column, primary) = bwt(data);
+            assert_eq!(inverse_bwt(&last_column, primary).unwrap(), *data);
+        }
+        assert!(matches!(inverse_bwt(b"ab", 2), Err(Error::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_move_to_front_and_zero_runs() {
+        assert_eq!(move_to_front(b"bbbaa"), vec![98, 0, 0, 98, 0]);
+        assert_eq!(inverse_move_to_front(&[98, 0, 0, 98, 0]), b"bbbaa");
+
+        // runs of 1 to 4 zeros are 0, 1, 00 and 10
+        assert_eq!(encode_zero_runs(&[0, 5, 0, 0, 5, 0, 0, 0, 5, 0, 0, 0, 0]), vec![0, 6, 1, 6, 0, 0, 6, 1, 0]);
+        let values: Vec<u8> = (0..=255).chain(std::iter::repeat_n(0, 1000)).collect();
+        let coded = encode_zero_runs(&values);
+        assert_eq!(&coded[253..258], &[254, 255, 0, 255, 1]);
+        assert_eq!(decode_zero_runs(&coded, values.len()).unwrap(), values);
+        assert!(matches!(decode_zero_runs(&coded, values.len() - 1), Err(Error::InvalidCode(_))));
+        assert!(matches!(decode_zero_runs(&[255, 2], 10), Err(Error::InvalidCode(_))));
+    }
+
+    #[test]
+    fn test_compress_roundtrip() {
+        let log = (0..500).map(|i| format!("2024-03-{:02} 12:{:02}:{:02} INFO worker-{} finished job {}\n",
+            i % 30 + 1, i % 60, i * 7 % 60, i % 4, i * 13)).collect::<String>();
+
+        let mut plain = HuffmanEncoder::new();
+        plain.add_chunk(log.as_bytes());
+        let plain = plain.encode();
+
+        let compressed = compress(log.as_bytes());
+        assert!(compressed.len() < plain.len() / 2);
+        assert_eq!(decompress(&compressed).unwrap(), log.as_bytes());
+
+        assert_eq!(decompress(&compress(b"")).unwrap(), b"");
+        assert_eq!(inverse_transform(&transform(b"x")).unwrap(), b"x");
+
+        let limits = DecodeLimits { max_output: 1000, ..DecodeLimits::default() };
+        assert!(matches!(decompress_with_limits(&compressed, &limits), Err(Error::LimitExceeded(_))));
+    }
+
+    #[test]
+    fn test_ratio_limit() {
+        // a block of zeros compresses to a few dozen bytes
+        let zeros = vec![0u8; 100_000];
+        let compressed = compress(&zeros);
+        assert!(compressed.len() < 100);
+
+        assert_eq!(decompress(&compressed).unwrap(), zeros);
+        match decompress_with_limits(&compressed, &DecodeLimits::default()) {
+            Err(Error::LimitExceeded("compression ratio")) => (),
+            other => panic!("unexpected result {:?}", other.map(|v| v.len())),
+        }
+    }
+
+    #[test]
+    fn test_invalid_blocks() {
+        let transformed = transform(b"hello hello hello");
+        assert!(matches!(inverse_transform(&transformed[..transformed.len() - 1]), Err(Error::UnexpectedEof)));
+
+        let mut bad_primary = transformed.clone();
+        bad_primary[4] = 17;
+        assert!(matches!(inverse_transform(&bad_primary), Err(Error::InvalidHeader(_))));
+
+        let mut bad_len = transformed;
+        bad_len[0] = 16;
+        assert!(matches!(inverse_transform(&bad_len), Err(Error::InvalidCode(_))));
+    }
+}
@@ -0,0 +1,285 @@
+use std::io;
+
+use crate::code_book::{Code, MAX_CODE_LEN};
+use crate::error::{Error, Result};
+use crate::freq_tree::combine_lightest;
+
+use bitstream::{BitWriter, ReadBits};
+
+/// Computes huffman code lengths for an alphabet of freqs.len() symbols using
+/// the same construction as the byte trees. Symbols with a count of 0 get a
+/// length of 0, meaning no code, except that at least two symbols always get
+/// a code. While a code would be longer than max_len all non-zero counts are
+/// halved, never dropping below 1, and the lengths are recomputed.
+///
+/// Panics if the alphabet has fewer than two symbols or if max_len is too
+/// small to give every symbol with a count its own code.
+pub fn code_lengths(freqs: &[u64], max_len: u32) -> Vec<u8> {
+    assert!(freqs.len() >= 2, "an alphabet needs at least two symbols");
+    let used = std::cmp::max(2, freqs.iter().filter(|f| **f > 0).count());
+    assert!(max_len <= MAX_CODE_LEN && (max_len >= 64 || (1u64 << max_len) >= used as u64),
+        "a length of {} cannot hold {} codes", max_len, used);
+
+    let mut freqs = freqs.to_vec();
+    loop {
+        let mut leaves: Vec<(usize, u64, Vec<usize>)> = freqs.iter().enumerate()
+            .filter(|(_, f)| **f > 0)
+            .map(|(s, f)| (s, *f, vec![s]))
+            .collect();
+        let mut unused = (0..freqs.len()).filter(|s| freqs[*s] == 0);
+        while leaves.len() < 2 {
+            let s = unused.next().unwrap();
+            leaves.push((s, 0, vec![s]));
+        }
+
+        // every merge puts the symbols of both subtrees one level deeper
+        let mut lengths = vec![0u8; freqs.len()];
+        combine_lightest(leaves, |mut left, right| {
+            left.extend(right);
+            for s in &left {
+                lengths[*s] += 1;
+            }
+            left
+        });
+
+        if lengths.iter().all(|l| *l as u32 <= max_len) {
+            return lengths;
+        }
+
+        for f in freqs.iter_mut().filter(|f| **f > 0) {
+            *f = std::cmp::max(1, *f / 2);
+        }
+    }
+}
+
+/// The longest code whose length write_packed_lengths() can store.
+pub(crate) const MAX_PACKED_CODE_LEN: u32 = 15;
+
+/// Appends code lengths to out, four bits each with the high bits first,
+/// padded with 0 bits to a byte boundary.
+pub(crate) fn write_packed_lengths(out: &mut Vec<u8>, lengths: &[u8]) {
+    out.extend(lengths.chunks(2).map(|pair| (pair[0] << 4) | pair.get(1).unwrap_or(&0)));
+}
+
+/// Reads count code lengths written by write_packed_lengths(). Returns the
+/// lengths and the rest of input.
+pub(crate) fn read_packed_lengths(input: &[u8], count: usize) -> Result<(Vec<u8>, &[u8])> {
+    let packed = input.get(..count.div_ceil(2)).ok_or(Error::UnexpectedEof)?;
+    let lengths = packed.iter().flat_map(|b| [b >> 4, b & 0x0f]).take(count).collect();
+    Ok((lengths, &input[packed.len()..]))
+}
+
+/// The state of a code that has been read partly, for decoders that are
+/// handed their input in pieces.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct PartialCode {
+    bits: u64,
+    len: u8,
+}
+
+impl PartialCode {
+    /// Returns the bits read so far, right aligned.
+    pub(crate) fn bits(&self) -> u64 {
+        self.bits
+    }
+
+    /// Returns the number of bits read so far.
+    pub(crate) fn len(&self) -> u8 {
+        self.len
+    }
+}
+
+/// A canonical prefix code over the symbols 0..n, defined by nothing but the
+/// code length of every symbol. Codes are assigned in order of length and
+/// then of symbol, each code being the next value after the previous one,
+/// which is how DEFLATE, JPEG and most other formats store their tables.
+///
+/// Unlike the byte trees, the alphabet may have any size and the code does
+/// not have to be complete.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanonicalCode {
+    lengths: Vec<u8>,
+    codes: Vec<Option<Code>>,
+    /// symbols with a code in canonical order
+    sorted: Vec<usize>,
+    /// for every length, the first code of that length, the number of codes
+    /// of that length and the position of the first of them in sorted
+    first_code: Vec<u64>,
+    counts: Vec<usize>,
+    first_index: Vec<usize>,
+}
+
+impl CanonicalCode {
+    /// Builds the code with the given length for every symbol, 0 meaning the
+    /// symbol has no code. Fails with Error::InvalidCode if the lengths are
+    /// longer than MAX_CODE_LEN or do not fit into a prefix code.
+    pub fn from_lengths(lengths: &[u8]) -> Result<CanonicalCode> {
+        let max_len = lengths.iter().cloned().max().unwrap_or(0) as usize;
+        if max_len as u32 > MAX_CODE_LEN {
+            return Err(Error::InvalidCode("code longer than the maximum code length"));
+        }
+
+        let mut counts = vec![0usize; max_len + 1];
+        for len in lengths.iter().filter(|l| **l > 0) {
+            counts[*len as usize] += 1;
+        }
+
+        // u128 since the first code after the last one of 64 bits needs 65
+        let mut first_code = vec![0u64; max_len + 1];
+        let mut first_index = vec![0usize; max_len + 1];
+        let mut next: u128 = 0;
+        let mut index = 0;
+        for len in 1..=max_len {
+            next <<= 1;
+            first_code[len] = next as u64;
+            first_index[len] = index;
+            next += counts[len] as u128;
+            index += counts[len];
+            if next > 1u128 << len {
+                return Err(Error::InvalidCode("code lengths are over-subscribed"));
+            }
+        }
+
+        let mut sorted: Vec<usize> = (0..lengths.len()).filter(|s| lengths[*s] > 0).collect();
+        sorted.sort_by_key(|s| lengths[*s]);
+
+        let mut codes = vec![None; lengths.len()];
+        let mut next_code = first_code.clone();
+        for s in &sorted {
+            let len = lengths[*s];
+            codes[*s] = Some(Code { bits: next_code[len as usize], len });
+            next_code[len as usize] += 1;
+        }
+
+        Ok(CanonicalCode {
+            lengths: lengths.to_vec(),
+            codes,
+            sorted,
+            first_code,
+            counts,
+            first_index,
+        })
+    }
+
+    /// Builds the canonical code with the lengths computed by code_lengths().
+    pub fn from_frequencies(freqs: &[u64], max_len: u32) -> CanonicalCode {
+        Self::from_lengths(&code_lengths(freqs, max_len)).expect("computed lengths form a code")
+    }
+
+    /// Returns the code length of every symbol, 0 for symbols without a code.
+    pub fn lengths(&self) -> &[u8] {
+        &self.lengths
+    }
+
+    /// Returns the number of symbols of the alphabet, including those
+    /// without a code.
+    pub fn alphabet_len(&self) -> usize {
+        self.lengths.len()
+    }
+
+    /// Returns the code of a symbol, or None if the symbol has no code.
+    pub fn code(&self, symbol: usize) -> Option<Code> {
+        self.codes.get(symbol).cloned().flatten()
+    }
+
+    /// Iterates over all symbols with a code and their codes in canonical
+    /// order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, Code)> + '_ {
+        self.sorted.iter().map(move |s| (*s, self.codes[*s].unwrap()))
+    }
+
+    /// Returns the length of the longest code.
+    pub fn max_len(&self) -> u32 {
+        self.counts.len() as u32 - 1
+    }
+
+    /// Writes the code of symbol to writer.
+    ///
+    /// Panics if the symbol has no code.
+    pub fn write_symbol(&self, writer: &mut BitWriter, symbol: usize) -> io::Result<()> {
+        let code = self.code(symbol).expect("symbol has no code");
+        writer.add_bits(code.bits, code.len)
+    }
+
+    /// Reads one code from reader and returns its symbol. Fails with
+    /// Error::UnexpectedEof if the reader ends within the code and with
+    /// Error::InvalidCode if the bits are not the start of any code.
+    pub fn read_symbol<R: ReadBits + ?Sized>(&self, reader: &mut R) -> Result<usize> {
+        let mut partial = PartialCode::default();
+        loop {
+            let bit = reader.get_bit().ok_or(Error::UnexpectedEof)?;
+            if let Some(symbol) = self.next_bit(&mut partial, bit)? {
+                return Ok(symbol);
+            }
+        }
+    }
+
+    /// Adds a bit to a partly read code. Returns the symbol and resets
+    /// partial once the code is complete.
+    pub(crate) fn next_bit(&self, partial: &mut PartialCode, bit: bool) -> Result<Option<usize>> {
+        let len = partial.len as usize + 1;
+        if len >= self.counts.len() {
+            return Err(Error::InvalidCode("bits do not match any code"));
+        }
+
+        let bits = (partial.bits << 1) | bit as u64;
+        let offset = bits.wrapping_sub(self.first_code[len]);
+        if bits >= self.first_code[len] && (offset as usize) < self.counts[len] {
+            *partial = PartialCode::default();
+            return Ok(Some(self.sorted[self.first_index[len] + offset as usize]));
+        }
+
+        *partial = PartialCode { bits, len: len as u8 };
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitstream::SliceBitReader;
+
+    #[test]
+    fn test_deflate_example() {
+        // the example of RFC 1951 section 3.2.2
+        let code = CanonicalCode::from_lengths(&[3, 3, 3, 3, 3, 2, 4, 4]).unwrap();
+        let codes: Vec<String> = (0..8).map(|s| code.code(s).unwrap().to_string()).collect();
+        assert_eq!(codes, vec!["010", "011", "100", "101", "110", "00", "1110", "1111"]);
+
+        let mut reader = SliceBitReader::new(&[0b1110_0111, 0b0100_0000]);
+        assert_eq!(code.read_symbol(&mut reader).unwrap(), 6);
+        assert_eq!(code.read_symbol(&mut reader).unwrap(), 1);
+        assert_eq!(code.read_symbol(&mut reader).unwrap(), 3);
+        assert_eq!(code.read_symbol(&mut reader).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_invalid_lengths() {
+        assert!(matches!(CanonicalCode::from_lengths(&[1, 1, 1]), Err(Error::InvalidCode(_))));
+
+        // an incomplete code is fine, but the missing codes cannot be read
+        let code = CanonicalCode::from_lengths(&[1, 2]).unwrap();
+        let mut reader = SliceBitReader::new(&[0xff]);
+        assert!(matches!(code.read_symbol(&mut reader), Err(Error::InvalidCode(_))));
+    }
+
+    #[test]
+    fn test_code_lengths() {
+        let mut freqs = vec![0u64; 300];
+        freqs[0] = 1000;
+        freqs[299] = 1;
+        freqs[7] = 1;
+        assert_eq!(code_lengths(&freqs, 15)[..8], [1, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(code_lengths(&freqs, 15)[299], 2);
+
+        // fibonacci counts make the deepest trees
+        let fib: Vec<u64> = (0..30).scan((1, 1), |s, _| { *s = (s.1, s.0 + s.1); Some(s.0) }).collect();
+        assert_eq!(code_lengths(&fib, 64).iter().cloned().max(), Some(29));
+        let limited = code_lengths(&fib, 10);
+        assert_eq!(limited.iter().cloned().max(), Some(10));
+        assert!(CanonicalCode::from_lengths(&limited).is_ok());
+
+        // padded to two codes
+        assert_eq!(code_lengths(&[0, 0, 5], 15), vec![1, 0, 1]);
+    }
+}
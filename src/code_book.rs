@@ -0,0 +1,104 @@
+use std::fmt;
+
+use crate::error::{Error, Result, TreeError};
+use crate::freq_tree::{FreqTreeNode, FreqNodeData};
+
+/// The longest code the encoder will produce and that a CodeBook can hold.
+pub const MAX_CODE_LEN: u32 = 64;
+
+/// The code assigned to a byte value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Code {
+    /// The bits of the code, right aligned. The first bit of the code is the
+    /// most significant of the len lowest bits.
+    pub bits: u64,
+    /// The number of bits in the code.
+    pub len: u8,
+}
+
+impl fmt::Display for Code {
+    /// Formats the code as a string of 0s and 1s.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in (0..self.len).rev() {
+            write!(f, "{}", (self.bits >> i) & 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// The codes assigned to every byte value of a huffman tree. A code is 0 for
+/// each step to a left child and 1 for each step to a right child on the path
+/// from the root to the byte's leaf.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodeBook {
+    codes: [Option<Code>; 256],
+    canonical_order: Vec<u8>,
+}
+
+impl CodeBook {
+    /// Builds the code book of a tree. Fails if the tree contains a byte
+    /// value more than once or has a code longer than MAX_CODE_LEN bits.
+    pub fn from_tree(tree: &FreqTreeNode) -> Result<CodeBook> {
+        let mut codes: [Option<Code>; 256] = [None; 256];
+        let mut stack = vec![(tree, Code { bits: 0, len: 0 })];
+
+        while let Some((node, code)) = stack.pop() {
+            match &node.data {
+                FreqNodeData::Composit(c) => {
+                    if code.len as u32 >= MAX_CODE_LEN {
+                        return Err(Error::InvalidTree(TreeError::TooDeep));
+                    }
+
+                    stack.push((c.right.as_ref(), Code { bits: (code.bits << 1) | 1, len: code.len + 1 }));
+                    stack.push((c.left.as_ref(), Code { bits: code.bits << 1, len: code.len + 1 }));
+                },
+                FreqNodeData::Value(v) => {
+                    let slot = &mut codes[v.byte_val as usize];
+                    if slot.is_some() {
+                        return Err(Error::InvalidTree(TreeError::DuplicateSymbol(v.byte_val)));
+                    }
+                    *slot = Some(code);
+                }
+            }
+        }
+
+        let mut canonical_order: Vec<u8> = (0..=255u8)
+            .filter(|b| codes[*b as usize].is_some())
+            .collect();
+        canonical_order.sort_by_key(|b| codes[*b as usize].unwrap().len);
+
+        Ok(CodeBook { codes, canonical_order })
+    }
+
+    /// Returns the code of a byte, or None if the byte has no code.
+    pub fn code(&self, byte: u8) -> Option<Code> {
+        self.codes[byte as usize]
+    }
+
+    /// Returns the number of bytes that have a code.
+    pub fn symbol_count(&self) -> usize {
+        self.canonical_order.len()
+    }
+
+    /// Iterates over all bytes with a code and their codes in canonical order,
+    /// which is by code length and then by byte value.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, Code)> + '_ {
+        self.canonical_order.iter().map(move |b| (*b, self.codes[*b as usize].unwrap()))
+    }
+
+    /// Returns the length of the longest code.
+    pub fn max_depth(&self) -> u32 {
+        self.iter().map(|(_, c)| c.len as u32).max().unwrap_or(0)
+    }
+
+    /// Returns the length of the shortest code.
+    pub fn min_depth(&self) -> u32 {
+        self.iter().map(|(_, c)| c.len as u32).min().unwrap_or(0)
+    }
+
+    /// Returns the sum of 2^-len over all codes. A code book built from a
+    /// complete tree has a Kraft sum of exactly 1.
+    pub fn kraft_sum(&self) -> f64 {
+        self.iter().map(|(_, c)| 0.5f64.powi(c.len as i32)).sum()
+    }
+}
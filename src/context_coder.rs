@@ -0,0 +1,251 @@
+//! An order-1 context model: every byte is coded with a huffman table chosen
+//! by the byte before it. This format is not the format of the
+//! HuffmanEncoder. It holds:
+//!
+//! * `u64` little endian: size of the original data
+//! * `u8`: the number of tables minus one
+//! * 256 bytes: the table used after each byte value
+//! * for every table a `u16` little endian size followed by the serialized ftree
+//! * the coded data
+//!
+//! The first byte is coded as if it followed a 0 byte.
+
+use std::cmp::min;
+use std::io::{self, Write};
+
+use crate::code_book::CodeBook;
+use crate::error::{Error, Result};
+use crate::freq_table::FrequencyTable;
+use crate::freq_tree::{FreqTreeNode, read_trees, write_trees};
+use crate::header::read_orig_size;
+use crate::huffman_decoder::DecodeLimits;
+
+use bitstream::{BitWriter, SliceBitReader};
+
+/// The estimated number of header bits a table spends on each of its leaves,
+/// which a merge of two contexts saves for every leaf they share.
+const LEAF_COST_BITS: f64 = 10.0;
+
+/// An encoder that codes every byte with a table chosen by the byte before it,
+/// an order-1 context model. Text and structured data often have far fewer
+/// likely successors for a given byte than overall, so the codes get shorter.
+///
+/// Contexts with similar statistics share a table to bound the size of the
+/// header: contexts are merged greedily, cheapest first, while merging saves
+/// more header space than it costs in coded data or while there are more
+/// tables than allowed by set_max_tables(). The data is decoded by the
+/// ContextDecoder.
+pub struct ContextEncoder {
+    contexts: Vec<FrequencyTable>,
+    data: Vec<u8>,
+    max_tables: usize,
+}
+
+impl Default for ContextEncoder {
+    fn default() -> ContextEncoder {
+        ContextEncoder::new()
+    }
+}
+
+impl Write for ContextEncoder {
+    /// Adds buf to the encoder like add_chunk(), in the context of the last
+    /// byte written. Never fails.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.add_chunk(buf);
+        Ok(buf.len())
+    }
+
+    /// Does nothing: the tables depend on all the data, so nothing can be
+    /// coded before encode().
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ContextEncoder {
+    /// Create a new encoder with no data that may use up to 256 tables.
+    pub fn new() -> ContextEncoder {
+        ContextEncoder {
+            contexts: vec![FrequencyTable::new(); 256],
+            data: Vec::new(),
+            max_tables: 256,
+        }
+    }
+
+    /// Limit the number of tables stored in the header.
+    ///
+    /// Panics if max_tables is not between 1 and 256.
+    pub fn set_max_tables(&mut self, max_tables: usize) {
+        assert!((1..=256).contains(&max_tables), "invalid table limit {}", max_tables);
+        self.max_tables = max_tables;
+    }
+
+    /// Add data to the encoder. The data continues the data added before it,
+    /// so its first byte is coded in the context of the last byte added.
+    pub fn add_chunk(&mut self, chunk: &[u8]) {
+        let mut previous = self.data.last().copied().unwrap_or(0);
+        for byte in chunk {
+            let count = self.contexts[previous as usize].get(*byte);
+            self.contexts[previous as usize].set(*byte, count + 1);
+            previous = *byte;
+        }
+        self.data.extend_from_slice(chunk);
+    }
+
+    /// Returns the counts of the bytes that follow the byte previous.
+    pub fn context_frequencies(&self, previous: u8) -> &FrequencyTable {
+        &self.contexts[previous as usize]
+    }
+
+    /// Returns the table used after every byte value, and the number of
+    /// tables, that encode() would use for the data added so far.
+    pub fn table_map(&self) -> ([u8; 256], usize) {
+        let (map, tables) = self.cluster();
+        (map, tables.len())
+    }
+
+    /// Encode all data added so far.
+    pub fn encode(&self) -> Vec<u8> {
+        let (map, tables) = self.cluster();
+        let trees: Vec<FreqTreeNode> = tables.iter().map(FreqTreeNode::from_frequencies).collect();
+        let code_books: Vec<CodeBook> = trees.iter()
+            .map(|t| CodeBook::from_tree(t).expect("built trees have valid codes"))
+            .collect();
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&(self.data.len() as u64).to_le_bytes());
+        out.push((tables.len() - 1) as u8);
+        out.extend_from_slice(&map);
+        write_trees(&mut out, &trees);
+
+        let mut bit_writer = BitWriter::with_writer(&mut out);
+        let mut previous = 0;
+        for byte in &self.data {
+            let code = code_books[map[previous as usize] as usize].code(*byte).unwrap();
+            bit_writer.add_bits(code.bits, code.len).unwrap();
+            previous = *byte;
+        }
+        bit_writer.flush().unwrap();
+        drop(bit_writer);
+        out
+    }
+
+    /// Groups the contexts into tables. Returns the table of every context
+    /// and the counts of every table.
+    fn cluster(&self) -> ([u8; 256], Vec<FrequencyTable>) {
+        let mut clusters: Vec<Option<(FrequencyTable, Vec<u8>)>> = (0..=255u8)
+            .filter(|b| !self.contexts[*b as usize].is_empty())
+            .map(|b| Some((self.contexts[b as usize].clone(), vec![b])))
+            .collect();
+        if clusters.is_empty() {
+            clusters.push(Some((FrequencyTable::new(), vec![0])));
+        }
+
+        // the cost of merging clusters i and j is kept at [i][j] with i < j
+        let n = clusters.len();
+        let mut costs: Vec<f64> = clusters.iter().map(|c| table_cost(&c.as_ref().unwrap().0)).collect();
+        let mut merge_costs: Vec<Vec<f64>> = (0..n)
+            .map(|i| (0..n).map(|j| if j > i { merge_cost(&clusters, &costs, i, j) } else { f64::INFINITY }).collect())
+            .collect();
+
+        let mut live = n;
+        while live > 1 {
+            let mut best = (f64::INFINITY, 0, 0);
+            for (i, row) in merge_costs.iter().enumerate() {
+                for (j, cost) in row.iter().enumerate().skip(i + 1) {
+                    if *cost < best.0 {
+                        best = (*cost, i, j);
+                    }
+                }
+            }
+
+            let (cost, i, j) = best;
+            if cost >= 0.0 && live <= self.max_tables {
+                break;
+            }
+
+            let (table, members) = clusters[j].take().unwrap();
+            let merged = clusters[i].as_mut().unwrap();
+            merged.0.merge(&table);
+            merged.1.extend(members);
+            costs[i] = table_cost(&merged.0);
+            live -= 1;
+
+            for k in 0..n {
+                merge_costs[min(k, j)][k.max(j)] = f64::INFINITY;
+                if k != i && clusters[k].is_some() {
+                    merge_costs[min(k, i)][k.max(i)] = merge_cost(&clusters, &costs, min(k, i), k.max(i));
+                }
+            }
+        }
+
+        let mut map = [0u8; 256];
+        let mut tables = Vec::new();
+        for (table, members) in clusters.into_iter().flatten() {
+            for context in members {
+                map[context as usize] = tables.len() as u8;
+            }
+            tables.push(table);
+        }
+        (map, tables)
+    }
+}
+
+/// Estimates the bits spent on a table: its coded data at the entropy of its
+/// counts plus its leaves in the header.
+fn table_cost(table: &FrequencyTable) -> f64 {
+    let total = table.total() as f64;
+    table.counts().iter()
+        .filter(|c| **c > 0)
+        .map(|c| *c as f64 * (total / *c as f64).log2() + LEAF_COST_BITS)
+        .sum()
+}
+
+/// The estimated number of bits merging clusters i and j adds.
+fn merge_cost(clusters: &[Option<(FrequencyTable, Vec<u8>)>], costs: &[f64], i: usize, j: usize) -> f64 {
+    let mut merged = clusters[i].as_ref().unwrap().0.clone();
+    merged.merge(&clusters[j].as_ref().unwrap().0);
+    table_cost(&merged) - costs[i] - costs[j]
+}
+
+/// Decoder for data encoded with the ContextEncoder.
+pub struct ContextDecoder {
+
+}
+
+impl ContextDecoder {
+    /// Decodes a buffer encoded with the ContextEncoder using the default
+    /// DecodeLimits.
+    pub fn decode(buf: &[u8]) -> Result<Vec<u8>> {
+        Self::decode_with_limits(buf, &DecodeLimits::default())
+    }
+
+    /// Decodes a buffer encoded with the ContextEncoder, failing with
+    /// Error::LimitExceeded if the data would exceed any of the limits. The
+    /// node limit applies to each table.
+    pub fn decode_with_limits(buf: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>> {
+        let header = buf.get(..8 + 1 + 256).ok_or(Error::UnexpectedEof)?;
+        let orig_size = read_orig_size(buf, limits)?;
+
+        let table_count = header[8] as usize + 1;
+        let map = &header[9..];
+        if map.iter().any(|t| *t as usize >= table_count) {
+            return Err(Error::InvalidHeader("context uses a missing table"));
+        }
+
+        let (trees, input) = read_trees(&buf[header.len()..], table_count, limits.max_tree_nodes)?;
+
+        let capacity = min(orig_size, input.len() as u64 * 8) as usize;
+        let mut out = Vec::with_capacity(capacity);
+        let mut breader = SliceBitReader::new(input);
+        let mut previous = 0;
+        for _ in 0..orig_size {
+            let tree = &trees[map[previous as usize] as usize];
+            let (byte, _) = tree.read_symbol(&mut breader).ok_or(Error::UnexpectedEof)?;
+            out.push(byte);
+            previous = byte;
+        }
+
+        Ok(out)
+    }
+}
@@ -0,0 +1,102 @@
+use std::fmt;
+use std::io;
+
+/// Errors that can occur while reading huffman encoded data.
+#[derive(Debug)]
+pub enum Error {
+    /// An error reported by the underlying reader or writer.
+    Io(io::Error),
+    /// The input ended before a complete structure could be read.
+    UnexpectedEof,
+    /// The header contained a value that is not understood by this crate.
+    InvalidHeader(&'static str),
+    /// A serialized frequency table was malformed.
+    InvalidFrequencyTable(&'static str),
+    /// A huffman tree was malformed.
+    InvalidTree(TreeError),
+    /// A prefix code was malformed or coded data did not match its code.
+    InvalidCode(&'static str),
+    /// The requested chunk does not exist.
+    NoSuchChunk { index: usize, count: usize },
+    /// The output buffer is too small to hold the result.
+    BufferTooSmall { needed: u64, available: usize },
+    /// Decoding was stopped because it would exceed one of the DecodeLimits.
+    LimitExceeded(&'static str),
+}
+
+/// The ways in which a huffman tree can be malformed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TreeError {
+    /// The serialized tree ended before every composit had two children.
+    Truncated,
+    /// The serialized tree has data left over after the tree is complete.
+    TrailingData,
+    /// The tree has a code longer than MAX_CODE_LEN.
+    TooDeep,
+    /// The tree has fewer than two leaves.
+    TooFewLeaves,
+    /// The byte value appears in more than one leaf.
+    DuplicateSymbol(u8),
+}
+
+impl fmt::Display for TreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TreeError::Truncated => write!(f, "tree data ends early"),
+            TreeError::TrailingData => write!(f, "data left over after the tree"),
+            TreeError::TooDeep => write!(f, "code longer than the maximum code length"),
+            TreeError::TooFewLeaves => write!(f, "fewer than two leaves"),
+            TreeError::DuplicateSymbol(b) => write!(f, "byte value {:#04x} appears more than once", b),
+        }
+    }
+}
+
+/// Result type used throughout the crate.
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::UnexpectedEof => write!(f, "unexpected end of input"),
+            Error::InvalidHeader(msg) => write!(f, "invalid header: {}", msg),
+            Error::InvalidFrequencyTable(msg) => write!(f, "invalid frequency table: {}", msg),
+            Error::InvalidTree(e) => write!(f, "invalid tree: {}", e),
+            Error::InvalidCode(msg) => write!(f, "invalid code: {}", msg),
+            Error::NoSuchChunk { index, count } =>
+                write!(f, "no chunk {}, the data has {} chunks", index, count),
+            Error::BufferTooSmall { needed, available } =>
+                write!(f, "buffer too small: {} bytes needed, {} available", needed, available),
+            Error::LimitExceeded(limit) => write!(f, "decode limit exceeded: {}", limit),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            Error::UnexpectedEof
+        } else {
+            Error::Io(e)
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Io(e) => e,
+            Error::UnexpectedEof => io::Error::new(io::ErrorKind::UnexpectedEof, e),
+            _ => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::{Error, Result};
+
+/// Number of bytes counted into the interleaved u32 tables of add_histogram()
+/// before they are folded into the u64 result, small enough that no u32 count
+/// can overflow.
+const HISTOGRAM_SEGMENT: usize = 1 << 30;
+
+/// Counts the occurences of every byte value in data.
+pub fn histogram(data: &[u8]) -> [u64; 256] {
+    let mut counts = [0u64; 256];
+    add_histogram(data, &mut counts);
+    counts
+}
+
+/// Adds the byte counts of data to counts.
+///
+/// Incrementing a single table stalls on runs of equal bytes since every
+/// increment has to wait for the store of the previous one to the same slot.
+/// Like zstd's HIST_count, the bytes are spread over four tables so that
+/// consecutive increments are independent of each other.
+fn add_histogram(data: &[u8], counts: &mut [u64; 256]) {
+    for segment in data.chunks(HISTOGRAM_SEGMENT) {
+        let mut tables = [[0u32; 256]; 4];
+
+        let mut words = segment.chunks_exact(4);
+        for word in &mut words {
+            tables[0][word[0] as usize] += 1;
+            tables[1][word[1] as usize] += 1;
+            tables[2][word[2] as usize] += 1;
+            tables[3][word[3] as usize] += 1;
+        }
+
+        for byte in words.remainder() {
+            tables[0][*byte as usize] += 1;
+        }
+
+        for (i, count) in counts.iter_mut().enumerate() {
+            *count += tables[0][i] as u64 + tables[1][i] as u64
+                + tables[2][i] as u64 + tables[3][i] as u64;
+        }
+    }
+}
+
+/// A table holding the number of occurences of every byte value. Tables can be
+/// built from sample data, merged together and serialized so that a code can be
+/// trained offline and shipped alongside the code that uses it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct FrequencyTable {
+    counts: [u64; 256],
+}
+
+impl FrequencyTable {
+    /// Create a table with a count of 0 for every byte.
+    pub fn new() -> FrequencyTable {
+        FrequencyTable { counts: [0; 256] }
+    }
+
+    /// Create a table from the byte counts of a sample.
+    pub fn from_data(data: &[u8]) -> FrequencyTable {
+        let mut table = FrequencyTable::new();
+        table.add_data(data);
+        table
+    }
+
+    /// Count the bytes of data into the table.
+    pub fn add_data(&mut self, data: &[u8]) {
+        add_histogram(data, &mut self.counts);
+    }
+
+    /// Returns the count of a byte.
+    pub fn get(&self, byte: u8) -> u64 {
+        self.counts[byte as usize]
+    }
+
+    /// Sets the count of a byte.
+    pub fn set(&mut self, byte: u8, count: u64) {
+        self.counts[byte as usize] = count;
+    }
+
+    /// Returns the counts of all bytes indexed by byte value.
+    pub fn counts(&self) -> &[u64; 256] {
+        &self.counts
+    }
+
+    /// Returns the sum of all counts.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().fold(0u64, |acc, c| acc.saturating_add(*c))
+    }
+
+    /// Returns true if every count is 0.
+    pub fn is_empty(&self) -> bool {
+        self.counts.iter().all(|c| *c == 0)
+    }
+
+    /// Adds the counts of other to this table. Counts saturate instead of
+    /// overflowing.
+    pub fn merge(&mut self, other: &FrequencyTable) {
+        for (mine, theirs) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *mine = mine.saturating_add(*theirs);
+        }
+    }
+
+    /// Serializes the table. Only the bytes with a non-zero count are stored:
+    /// a `u16` little endian entry count followed by the entries, each being the
+    /// byte value and its `u64` little endian count, in ascending byte order.
+    pub fn encode(&self) -> Vec<u8> {
+        let entries: Vec<(usize, u64)> = self.counts.iter()
+            .cloned()
+            .enumerate()
+            .filter(|(_, c)| *c > 0)
+            .collect();
+
+        let mut encoded = Vec::with_capacity(2 + entries.len() * 9);
+        encoded.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for (byte, count) in entries {
+            encoded.push(byte as u8);
+            encoded.extend_from_slice(&count.to_le_bytes());
+        }
+
+        encoded
+    }
+
+    /// Deserializes a table produced by the encode function.
+    pub fn decode(data: &[u8]) -> Result<FrequencyTable> {
+        if data.len() < 2 {
+            return Err(Error::UnexpectedEof);
+        }
+
+        let entry_count = u16::from_le_bytes([data[0], data[1]]) as usize;
+        if entry_count > 256 {
+            return Err(Error::InvalidFrequencyTable("more than 256 entries"));
+        }
+
+        let entries = &data[2..];
+        if entries.len() < entry_count * 9 {
+            return Err(Error::UnexpectedEof);
+        }
+        if entries.len() > entry_count * 9 {
+            return Err(Error::InvalidFrequencyTable("trailing data"));
+        }
+
+        let mut table = FrequencyTable::new();
+        let mut last_byte: Option<u8> = None;
+        for entry in entries.chunks_exact(9) {
+            let byte = entry[0];
+            if last_byte.is_some_and(|b| b >= byte) {
+                return Err(Error::InvalidFrequencyTable("entries not in ascending order"));
+            }
+
+            let mut count_bytes = [0u8; 8];
+            count_bytes.copy_from_slice(&entry[1..]);
+            let count = u64::from_le_bytes(count_bytes);
+            if count == 0 {
+                return Err(Error::InvalidFrequencyTable("entry with a zero count"));
+            }
+
+            table.counts[byte as usize] = count;
+            last_byte = Some(byte);
+        }
+
+        Ok(table)
+    }
+}
+
+impl Default for FrequencyTable {
+    fn default() -> FrequencyTable {
+        FrequencyTable::new()
+    }
+}
+
+impl fmt::Debug for FrequencyTable {
+    /// Only the bytes with a non-zero count are shown.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(self.counts.iter().enumerate().filter(|(_, c)| **c > 0))
+            .finish()
+    }
+}
+
+impl From<[u64; 256]> for FrequencyTable {
+    fn from(counts: [u64; 256]) -> FrequencyTable {
+        FrequencyTable { counts }
+    }
+}
+
+impl From<&HashMap<u8, u64>> for FrequencyTable {
+    fn from(map: &HashMap<u8, u64>) -> FrequencyTable {
+        let mut table = FrequencyTable::new();
+        for (byte, count) in map {
+            table.counts[*byte as usize] = *count;
+        }
+        table
+    }
+}
+
+impl From<HashMap<u8, u64>> for FrequencyTable {
+    fn from(map: HashMap<u8, u64>) -> FrequencyTable {
+        FrequencyTable::from(&map)
+    }
+}
+
+impl From<&FrequencyTable> for HashMap<u8, u64> {
+    fn from(table: &FrequencyTable) -> HashMap<u8, u64> {
+        table.counts.iter()
+            .enumerate()
+            .filter(|(_, c)| **c > 0)
+            .map(|(b, c)| (b as u8, *c))
+            .collect()
+    }
+}
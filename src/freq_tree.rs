@@ -1,15 +1,26 @@
-use std::cmp::Ordering;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::code_book::MAX_CODE_LEN;
+use crate::error::{Error, Result, TreeError};
+use crate::freq_table::FrequencyTable;
 
 use bitstream::*;
 
 /// A node of the frequency tree required for huffman encoding. The data of 
 /// this node is either a composit of nodes or a value which is a byte with
 /// a frequency. 
+///
+/// Equality is structural. Nodes are ordered by weight first, then by depth
+/// and then by their smallest byte value, which is the order in which nodes
+/// are combined when a tree is built.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct FreqTreeNode {
     pub data: FreqNodeData,
 }
 
 /// The payload of an ftree node. 
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum FreqNodeData {
     Composit (FreqTreeComposit),
     Value (FreqTreeVal),
@@ -17,12 +28,14 @@ pub enum FreqNodeData {
 
 /// A leaf node of the ftree. Stores its byte value and the number of occurences
 /// of the byte
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct FreqTreeVal {
     pub byte_val: u8,
     pub occures: u64
 }
 
 /// A subtree node of the ftree. It stores the sum of all leafs below the tree.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct FreqTreeComposit {
     pub occures: u64,
     pub left: Box<FreqTreeNode>,
@@ -30,6 +43,73 @@ pub struct FreqTreeComposit {
 }
 
 impl FreqTreeNode {
+    /// Create a leaf node for a byte value.
+    pub fn leaf(byte_val: u8, occures: u64) -> FreqTreeNode {
+        FreqTreeNode { data: FreqNodeData::Value(FreqTreeVal { byte_val, occures }) }
+    }
+
+    /// Create a composit node whose weight is the sum of its children.
+    pub fn composit(left: FreqTreeNode, right: FreqTreeNode) -> FreqTreeNode {
+        FreqTreeNode { data: FreqNodeData::Composit(FreqTreeComposit {
+            occures: left.get_weight().saturating_add(right.get_weight()),
+            left: Box::new(left),
+            right: Box::new(right),
+        })}
+    }
+
+    /// Builds the huffman tree for a frequency table. Bytes with a count of 0
+    /// are left out of the tree, except that the tree is padded out to at least
+    /// two leaves so that every byte has a code of at least one bit. The depth
+    /// of the tree is limited to MAX_CODE_LEN.
+    pub fn from_frequencies(table: &FrequencyTable) -> FreqTreeNode {
+        Self::from_frequencies_limited(table, MAX_CODE_LEN)
+    }
+
+    /// Builds the huffman tree for a frequency table like from_frequencies()
+    /// but with a depth of at most max_depth. While the tree is too deep all
+    /// non-zero counts are halved, never dropping below 1, and the tree is
+    /// rebuilt, which flattens the skewed parts of the tree first.
+    ///
+    /// Panics if max_depth is too small to give every leaf its own code.
+    pub fn from_frequencies_limited(table: &FrequencyTable, max_depth: u32) -> FreqTreeNode {
+        let leaves = std::cmp::max(2, table.counts().iter().filter(|c| **c > 0).count());
+        assert!(max_depth >= 64 || (1u64 << max_depth) >= leaves as u64,
+            "a depth of {} cannot hold {} leaves", max_depth, leaves);
+
+        let mut table = table.clone();
+        loop {
+            let tree = Self::build(&table);
+            if tree.depth() <= max_depth {
+                return tree;
+            }
+
+            for byte in 0..=255u8 {
+                let count = table.get(byte);
+                if count > 0 {
+                    table.set(byte, std::cmp::max(1, count / 2));
+                }
+            }
+        }
+    }
+
+    fn build(table: &FrequencyTable) -> FreqTreeNode {
+        // add all of the values to the heap as Value nodes
+        let mut leaves: Vec<(usize, u64, FreqTreeNode)> = Vec::new();
+        for (k, v) in table.counts().iter().enumerate() {
+            if *v > 0 {
+                leaves.push((k, *v, FreqTreeNode::leaf(k as u8, *v)));
+            }
+        }
+
+        let mut unused = (0..=255u8).filter(|b| table.get(*b) == 0);
+        while leaves.len() < 2 {
+            let byte = unused.next().unwrap();
+            leaves.push((byte as usize, 0, FreqTreeNode::leaf(byte, 0)));
+        }
+
+        combine_lightest(leaves, FreqTreeNode::composit)
+    }
+
     /// Returns the weight of an ftree node. If the node is a leaf it returns
     /// the number of occurences for the byte val. If the node is a composit
     /// then it returns the value of all leaves below it.
@@ -41,25 +121,138 @@ impl FreqTreeNode {
         }
     }
 
+    /// Returns the number of levels below this node, which is the length of
+    /// the longest code in the subtree. A leaf has a depth of 0.
+    pub fn depth(&self) -> u32 {
+        match &self.data {
+            FreqNodeData::Composit(c) => 1 + std::cmp::max(c.left.depth(), c.right.depth()),
+            FreqNodeData::Value(_) => 0,
+        }
+    }
+
+    /// Returns the number of leaves below this node.
+    pub fn leaf_count(&self) -> usize {
+        match &self.data {
+            FreqNodeData::Composit(c) => c.left.leaf_count() + c.right.leaf_count(),
+            FreqNodeData::Value(_) => 1,
+        }
+    }
+
+    /// Returns the number of bytes the encode function produces. Every leaf
+    /// takes 9 bits and every composit 1 bit.
+    pub fn serialized_len(&self) -> usize {
+        (10 * self.leaf_count() - 1).div_ceil(8)
+    }
+
+    /// Returns the smallest byte value of all leaves below this node.
+    pub fn min_symbol(&self) -> u8 {
+        match &self.data {
+            FreqNodeData::Composit(c) => std::cmp::min(c.left.min_symbol(), c.right.min_symbol()),
+            FreqNodeData::Value(v) => v.byte_val,
+        }
+    }
+
     /// Decodes an ftree produced from the encode function
-    pub fn decode(data: &[u8]) -> FreqTreeNode {
-        let mut vs = VecStream::from_vec(data.to_owned());
-        let mut br = BitReader::with_reader(&mut vs);
-
-        Self::decode_node(&mut br)
-    }
-
-    fn decode_node(reader: &mut BitReader) -> FreqTreeNode {
-        if reader.get_bit().unwrap() {
-            FreqTreeNode { data: FreqNodeData::Value ( FreqTreeVal { byte_val: reader.get_byte().unwrap(), occures: 0 } ) }
-        } else {
-            FreqTreeNode { data: FreqNodeData::Composit (
-                FreqTreeComposit {
-                    occures: 0,
-                    left: Box::new(Self::decode_node(reader)),
-                    right: Box::new(Self::decode_node(reader)),
+    pub fn decode(data: &[u8]) -> Result<FreqTreeNode> {
+        Self::decode_with_limit(data, usize::MAX)
+    }
+
+    /// Decodes an ftree produced from the encode function, failing with
+    /// Error::LimitExceeded once more than max_nodes nodes have been read.
+    ///
+    /// The tree is parsed without recursion and is checked to be a tree the
+    /// encoder could have produced: it must have at least two leaves, no byte
+    /// value may appear twice, no code may be longer than MAX_CODE_LEN and
+    /// the data must end with the tree, apart from zero bits padding out the
+    /// last byte.
+    pub fn decode_with_limit(data: &[u8], max_nodes: usize) -> Result<FreqTreeNode> {
+        let mut reader = SliceBitReader::new(data);
+        let truncated = || Error::InvalidTree(TreeError::Truncated);
+
+        // composits whose children are still being read, along with their left
+        // child once it is complete
+        let mut pending: Vec<Option<FreqTreeNode>> = Vec::new();
+        let mut seen = [false; 256];
+        let mut nodes = 0;
+        let mut bits = 0;
+
+        let root = loop {
+            if nodes == max_nodes {
+                return Err(Error::LimitExceeded("tree nodes"));
+            }
+            nodes += 1;
+            bits += 1;
+
+            if !reader.get_bit().ok_or_else(truncated)? {
+                if pending.len() as u32 >= MAX_CODE_LEN {
+                    return Err(Error::InvalidTree(TreeError::TooDeep));
                 }
-            )}
+                pending.push(None);
+                continue;
+            }
+
+            let byte_val = reader.get_byte().ok_or_else(truncated)?;
+            bits += 8;
+            if seen[byte_val as usize] {
+                return Err(Error::InvalidTree(TreeError::DuplicateSymbol(byte_val)));
+            }
+            seen[byte_val as usize] = true;
+
+            // attach the finished node to its parent, finishing every parent
+            // that now has both children
+            let mut node = FreqTreeNode::leaf(byte_val, 0);
+            let finished = loop {
+                match pending.pop() {
+                    None => break Some(node),
+                    Some(None) => {
+                        pending.push(Some(node));
+                        break None;
+                    },
+                    Some(Some(left)) => node = FreqTreeNode::composit(left, node),
+                }
+            };
+
+            if let Some(root) = finished {
+                break root;
+            }
+        };
+
+        if nodes == 1 {
+            return Err(Error::InvalidTree(TreeError::TooFewLeaves));
+        }
+
+        while bits % 8 != 0 {
+            if reader.get_bit() != Some(false) {
+                return Err(Error::InvalidTree(TreeError::TrailingData));
+            }
+            bits += 1;
+        }
+        if reader.get_bit().is_some() {
+            return Err(Error::InvalidTree(TreeError::TrailingData));
+        }
+
+        Ok(root)
+    }
+
+    /// Walks down the tree from this node, taking the left child for a 0 bit
+    /// and the right child for a 1 bit, until a leaf is reached. Returns the
+    /// byte value of the leaf and the number of bits consumed, or None if the
+    /// reader runs out of bits first.
+    pub fn read_symbol<R: ReadBits + ?Sized>(&self, reader: &mut R) -> Option<(u8, u64)> {
+        let mut at = self;
+        let mut bits = 0;
+
+        loop {
+            let bit = reader.get_bit()?;
+            bits += 1;
+
+            if let FreqNodeData::Composit(c) = &at.data {
+                at = if bit { c.right.as_ref() } else { c.left.as_ref() };
+            }
+
+            if let FreqNodeData::Value(v) = &at.data {
+                return Some((v.byte_val, bits));
+            }
         }
     }
 
@@ -89,30 +282,218 @@ impl FreqTreeNode {
     }
 }
 
-impl PartialEq for FreqTreeNode {
+impl PartialOrd for FreqTreeNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FreqTreeNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let my_key = (self.get_weight(), self.depth(), self.min_symbol());
+        let other_key = (other.get_weight(), other.depth(), other.min_symbol());
+        my_key.cmp(&other_key).then_with(|| self.data.cmp(&other.data))
+    }
+}
+
+/// An entry of the heap used to build huffman trees, ordered the same way
+/// FreqTreeNodes are: by weight, then by depth and then by smallest symbol.
+struct HeapEntry<T> {
+    weight: u64,
+    depth: u32,
+    min_symbol: usize,
+    item: T,
+}
+
+impl<T> HeapEntry<T> {
+    fn key(&self) -> (u64, u32, usize) {
+        (self.weight, self.depth, self.min_symbol)
+    }
+}
+
+impl<T> PartialEq for HeapEntry<T> {
     fn eq(&self, other: &Self) -> bool {
-        self.cmp(other) != Ordering::Equal
+        self.key() == other.key()
     }
 }
 
-impl Eq for FreqTreeNode {}
+impl<T> Eq for HeapEntry<T> {}
 
-impl PartialOrd for FreqTreeNode {
+impl<T> PartialOrd for HeapEntry<T> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl Ord for FreqTreeNode {
+impl<T> Ord for HeapEntry<T> {
     fn cmp(&self, other: &Self) -> Ordering {
-        let my_weight = self.get_weight();
-        let other_weight = other.get_weight();
-        if my_weight < other_weight {
-            Ordering::Greater
-        } else if my_weight > other_weight {
-            Ordering::Less
-        } else {
-            Ordering::Equal
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Appends every tree to out, serialized and preceded by its `u16` little
+/// endian size.
+pub(crate) fn write_trees(out: &mut Vec<u8>, trees: &[FreqTreeNode]) {
+    for tree in trees {
+        let serialized = tree.encode();
+        out.extend_from_slice(&(serialized.len() as u16).to_le_bytes());
+        out.extend_from_slice(&serialized);
+    }
+}
+
+/// Reads count trees written by write_trees(), each held to max_nodes nodes.
+/// Returns the trees and the rest of input.
+pub(crate) fn read_trees(mut input: &[u8], count: usize, max_nodes: usize) -> Result<(Vec<FreqTreeNode>, &[u8])> {
+    let mut trees = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len_bytes = input.get(..2).ok_or(Error::UnexpectedEof)?;
+        let len = u16::from_le_bytes([len_bytes[0], len_bytes[1]]) as usize;
+        let serialized = input.get(2..2 + len).ok_or(Error::UnexpectedEof)?;
+        trees.push(FreqTreeNode::decode_with_limit(serialized, max_nodes)?);
+        input = &input[2 + len..];
+    }
+    Ok((trees, input))
+}
+
+/// Runs the huffman construction over (symbol, weight, item) leaves: the two
+/// lightest entries are passed to combine, lighter one first, until a single
+/// item is left. Symbols must be unique, which makes the result independent
+/// of the order of the leaves. Used for the byte trees as well as for the
+/// code lengths of larger alphabets.
+///
+/// Panics if leaves is empty.
+pub(crate) fn combine_lightest<T, F>(leaves: Vec<(usize, u64, T)>, mut combine: F) -> T
+    where F: FnMut(T, T) -> T
+{
+    let mut heap: BinaryHeap<Reverse<HeapEntry<T>>> = leaves.into_iter()
+        .map(|(min_symbol, weight, item)| Reverse(HeapEntry { weight, depth: 0, min_symbol, item }))
+        .collect();
+
+    while heap.len() > 1 {
+        let Reverse(left) = heap.pop().unwrap();
+        let Reverse(right) = heap.pop().unwrap();
+        heap.push(Reverse(HeapEntry {
+            weight: left.weight.saturating_add(right.weight),
+            depth: 1 + std::cmp::max(left.depth, right.depth),
+            min_symbol: std::cmp::min(left.min_symbol, right.min_symbol),
+            item: combine(left.item, right.item),
+        }));
+    }
+
+    heap.pop().expect("no leaves to combine").0.item
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::code_book::CodeBook;
+    use super::*;
+
+    #[test]
+    fn freq_tree_equality_is_structural() {
+        let a = FreqTreeNode::composit(FreqTreeNode::leaf(b'a', 1), FreqTreeNode::leaf(b'b', 2));
+        let b = FreqTreeNode::composit(FreqTreeNode::leaf(b'b', 2), FreqTreeNode::leaf(b'a', 1));
+
+        assert_eq!(a, a.clone());
+        assert_ne!(a, b);
+        assert_ne!(a.cmp(&b), Ordering::Equal);
+        assert_eq!(FreqTreeNode::leaf(b'x', 3), FreqTreeNode::leaf(b'x', 3));
+        assert_ne!(FreqTreeNode::leaf(b'x', 3), FreqTreeNode::leaf(b'y', 3));
+    }
+
+    #[test]
+    fn freq_tree_ordering() {
+        let light = FreqTreeNode::leaf(b'z', 1);
+        let heavy = FreqTreeNode::leaf(b'a', 5);
+        let shallow = FreqTreeNode::leaf(b'z', 3);
+        let deep = FreqTreeNode::composit(FreqTreeNode::leaf(b'a', 1), FreqTreeNode::leaf(b'b', 2));
+
+        assert!(light < heavy);
+        assert!(shallow < deep);
+        assert!(FreqTreeNode::leaf(b'a', 3) < shallow);
+    }
+
+    #[test]
+    fn freq_tree_decode_errors() {
+        let invalid = |data: &[u8]| match FreqTreeNode::decode(data) {
+            Err(Error::InvalidTree(e)) => e,
+            other => panic!("unexpected result {:?}", other),
+        };
+
+        // 0, 1 'a' and then nothing for the right child
+        assert_eq!(invalid(&[0b0101_1000, 0b0100_0000]), TreeError::Truncated);
+        // 0, 1 'a', 1 'a'
+        assert_eq!(invalid(&[0b0101_1000, 0b0110_1100, 0b0010_0000]), TreeError::DuplicateSymbol(b'a'));
+        // 1 'a'
+        assert_eq!(invalid(&[0b1011_0000, 0b1000_0000]), TreeError::TooFewLeaves);
+        // a tree followed by an extra byte
+        let mut extra = FreqTreeNode::composit(FreqTreeNode::leaf(1, 0), FreqTreeNode::leaf(2, 0)).encode();
+        extra.push(0);
+        assert_eq!(invalid(&extra), TreeError::TrailingData);
+        // non zero padding
+        let mut padded = FreqTreeNode::composit(FreqTreeNode::leaf(1, 0), FreqTreeNode::leaf(2, 0)).encode();
+        *padded.last_mut().unwrap() |= 1;
+        assert_eq!(invalid(&padded), TreeError::TrailingData);
+
+        // a long run of 0 bits must not overflow the stack
+        assert_eq!(invalid(&[0u8; 100_000]), TreeError::TooDeep);
+
+        match FreqTreeNode::decode_with_limit(&[0u8; 10], 5) {
+            Err(Error::LimitExceeded(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn freq_tree_depth_limit() {
+        // fibonacci counts produce a tree that is as deep as possible
+        let mut table = FrequencyTable::new();
+        let (mut a, mut b) = (1u64, 1u64);
+        for byte in 0..40u8 {
+            table.set(byte, a);
+            let next = a + b;
+            a = b;
+            b = next;
         }
+
+        assert_eq!(FreqTreeNode::from_frequencies(&table).depth(), 39);
+
+        let limited = FreqTreeNode::from_frequencies_limited(&table, 12);
+        assert!(limited.depth() <= 12);
+        assert_eq!(CodeBook::from_tree(&limited).unwrap().symbol_count(), 40);
+    }
+
+    #[test]
+    fn freq_tree_build_and_roundtrip() {
+        let table = FrequencyTable::from_data(b"aaaabbc");
+        let tree = FreqTreeNode::from_frequencies(&table);
+
+        let expected = FreqTreeNode::composit(
+            FreqTreeNode::composit(FreqTreeNode::leaf(b'c', 1), FreqTreeNode::leaf(b'b', 2)),
+            FreqTreeNode::leaf(b'a', 4),
+        );
+        assert_eq!(tree, expected);
+        assert_eq!(tree.get_weight(), 7);
+        assert_eq!(tree.depth(), 2);
+
+        assert_eq!(tree.leaf_count(), 3);
+        assert_eq!(tree.serialized_len(), tree.encode().len());
+
+        // weights are not serialized
+        let decoded = FreqTreeNode::decode(&tree.encode()).unwrap();
+        assert_eq!(decoded.encode(), tree.encode());
+        assert_eq!(decoded.get_weight(), 0);
+    }
+
+    #[test]
+    fn freq_tree_huge_counts() {
+        let table = FrequencyTable::from([u64::MAX / 2; 256]);
+        let tree = FreqTreeNode::from_frequencies(&table);
+        assert_eq!(tree.get_weight(), u64::MAX);
+        assert_eq!(tree.leaf_count(), 256);
+        assert_eq!(CodeBook::from_tree(&tree).unwrap().symbol_count(), 256);
+
+        let mut decoded = FreqTreeNode::decode(&tree.encode()).unwrap();
+        decoded.assign_weights(&table);
+        assert_eq!(decoded.get_weight(), u64::MAX);
     }
 }
@@ -0,0 +1,315 @@
+//! The parts of finite state entropy coding that zstd uses to compress the
+//! weights of its huffman tables: the normalized count header and a stream
+//! of two interleaved states.
+
+use crate::error::{Error, Result};
+
+use bitstream::{BackwardBitReader, BackwardBitWriter};
+
+/// The smallest table log the count header can describe.
+const MIN_TABLE_LOG: u8 = 5;
+
+/// An entry of the decoding table: the symbol of a state, and how to get to
+/// the next state from the bits that follow.
+#[derive(Clone, Copy, Debug, Default)]
+struct DecodeEntry {
+    symbol: u8,
+    nb_bits: u8,
+    base: u16,
+}
+
+/// Spreads the symbols over the states of a table of size 1 << table_log.
+/// Symbols with a count of -1 take one state each at the end of the table.
+fn spread(norm: &[i16], table_log: u8) -> Result<Vec<u8>> {
+    let size = 1usize << table_log;
+    let mut table = vec![0u8; size];
+    let mut high = size - 1;
+    for (s, count) in norm.iter().enumerate() {
+        if *count == -1 {
+            table[high] = s as u8;
+            high = high.checked_sub(1).ok_or(Error::InvalidCode("too many low probability symbols"))?;
+        }
+    }
+
+    let step = (size >> 1) + (size >> 3) + 3;
+    let mask = size - 1;
+    let mut pos = 0;
+    for (s, count) in norm.iter().enumerate() {
+        for _ in 0..std::cmp::max(0, *count) {
+            table[pos] = s as u8;
+            pos = (pos + step) & mask;
+            while pos > high {
+                pos = (pos + step) & mask;
+            }
+        }
+    }
+
+    if pos != 0 {
+        return Err(Error::InvalidCode("counts do not fill the table"));
+    }
+    Ok(table)
+}
+
+/// The first state number of every symbol, which the states of the symbol
+/// count up from.
+fn first_states(norm: &[i16]) -> Vec<u32> {
+    norm.iter().map(|c| if *c == -1 { 1 } else { std::cmp::max(0, *c) as u32 }).collect()
+}
+
+fn highbit(val: u32) -> u8 {
+    31 - val.leading_zeros() as u8
+}
+
+/// Reads a normalized count header of symbols up to max_symbol. Returns the
+/// counts, the table log and the number of bytes used.
+pub(crate) fn read_counts(buf: &[u8], max_symbol: usize, max_table_log: u8) -> Result<(Vec<i16>, u8, usize)> {
+    let mut bit_pos = 0;
+    let mut read = |count: u8, consume: u8| -> Result<u32> {
+        let mut val = 0;
+        for i in 0..count as usize {
+            let pos = bit_pos + i;
+            let byte = *buf.get(pos / 8).ok_or(Error::UnexpectedEof)?;
+            val |= (((byte >> (pos % 8)) & 1) as u32) << i;
+        }
+        bit_pos += consume as usize;
+        Ok(val)
+    };
+
+    let table_log = read(4, 4)? as u8 + MIN_TABLE_LOG;
+    if table_log > max_table_log {
+        return Err(Error::InvalidCode("table log too large"));
+    }
+
+    let mut remaining: i32 = (1 << table_log) + 1;
+    let mut threshold: i32 = 1 << table_log;
+    let mut nb_bits = table_log + 1;
+    let mut norm: Vec<i16> = Vec::new();
+    let mut previous_zero = false;
+
+    while remaining > 1 {
+        if previous_zero {
+            loop {
+                let repeat = read(2, 2)?;
+                norm.extend(std::iter::repeat_n(0, repeat as usize));
+                if repeat != 3 {
+                    break;
+                }
+            }
+        }
+        if norm.len() > max_symbol {
+            return Err(Error::InvalidCode("too many symbols"));
+        }
+
+        let max = (2 * threshold - 1) - remaining;
+        let low = read(nb_bits - 1, 0)? as i32;
+        let value = if low & (threshold - 1) < max {
+            read(nb_bits - 1, nb_bits - 1)? as i32
+        } else {
+            let value = read(nb_bits, nb_bits)? as i32;
+            if value >= threshold { value - max } else { value }
+        };
+
+        let count = value - 1;
+        remaining -= count.abs();
+        norm.push(count as i16);
+        previous_zero = count == 0;
+        while remaining < threshold {
+            nb_bits -= 1;
+            threshold >>= 1;
+        }
+    }
+
+    if remaining != 1 {
+        return Err(Error::InvalidCode("counts do not add up"));
+    }
+
+    Ok((norm, table_log, bit_pos.div_ceil(8)))
+}
+
+/// Writes the normalized count header, the inverse of read_counts().
+pub(crate) fn write_counts(norm: &[i16], table_log: u8, out: &mut Vec<u8>) {
+    let mut bits: u64 = 0;
+    let mut bit_count = 0;
+    let mut write = |val: u32, count: u8| {
+        bits |= (val as u64) << bit_count;
+        bit_count += count;
+        while bit_count >= 8 {
+            out.push(bits as u8);
+            bits >>= 8;
+            bit_count -= 8;
+        }
+    };
+
+    write((table_log - MIN_TABLE_LOG) as u32, 4);
+
+    let mut remaining: i32 = (1 << table_log) + 1;
+    let mut threshold: i32 = 1 << table_log;
+    let mut nb_bits = table_log + 1;
+    let mut symbol = 0;
+    let mut previous_zero = false;
+
+    while remaining > 1 {
+        if previous_zero {
+            let start = symbol;
+            while norm[symbol] == 0 {
+                symbol += 1;
+            }
+            let mut zeros = symbol - start;
+            while zeros >= 3 {
+                write(3, 2);
+                zeros -= 3;
+            }
+            write(zeros as u32, 2);
+        }
+
+        let count = norm[symbol] as i32;
+        symbol += 1;
+        let max = (2 * threshold - 1) - remaining;
+        remaining -= count.abs();
+        let mut value = count + 1;
+        if value >= threshold {
+            value += max;
+        }
+        write(value as u32, if value < max { nb_bits - 1 } else { nb_bits });
+        previous_zero = value == 1;
+        while remaining < threshold {
+            nb_bits -= 1;
+            threshold >>= 1;
+        }
+    }
+
+    write(0, 7);
+}
+
+/// Scales counts to add up to 1 << table_log, keeping every symbol that
+/// occurs at a count of at least 1. Returns None if the counts cannot be
+/// scaled because there are too many symbols.
+pub(crate) fn normalize(counts: &[u32], table_log: u8) -> Option<Vec<i16>> {
+    let total: u64 = counts.iter().map(|c| *c as u64).sum();
+    let size = 1i64 << table_log;
+    let mut norm: Vec<i16> = counts.iter()
+        .map(|c| if *c == 0 { 0 } else { std::cmp::max(1, (*c as i64 * size + total as i64 / 2) / total as i64) as i16 })
+        .collect();
+
+    let sum: i64 = norm.iter().map(|n| *n as i64).sum();
+    let largest = (0..norm.len()).max_by_key(|s| norm[*s])?;
+    let fixed = norm[largest] as i64 + size - sum;
+    if fixed < 1 {
+        return None;
+    }
+    norm[largest] = fixed as i16;
+    Some(norm)
+}
+
+/// Decodes a stream of two interleaved states. The number of symbols is not
+/// stored; decoding stops once a state update reads past the start of the
+/// stream, after which the other state yields the last symbol.
+pub(crate) fn decode(buf: &[u8], norm: &[i16], table_log: u8, max_symbols: usize) -> Result<Vec<u8>> {
+    let symbols = spread(norm, table_log)?;
+    let mut next = first_states(norm);
+    let size = 1u32 << table_log;
+    let table: Vec<DecodeEntry> = symbols.iter().map(|s| {
+        let state = next[*s as usize];
+        next[*s as usize] += 1;
+        let nb_bits = table_log - highbit(state);
+        DecodeEntry { symbol: *s, nb_bits, base: ((state << nb_bits) - size) as u16 }
+    }).collect();
+
+    let mut reader = BackwardBitReader::new(buf).ok_or(Error::InvalidCode("stream has no end marker"))?;
+    let mut states = [reader.read_bits(table_log) as usize, reader.read_bits(table_log) as usize];
+    if reader.overflowed() {
+        return Err(Error::UnexpectedEof);
+    }
+
+    let mut out = Vec::new();
+    for i in (0..2).cycle() {
+        if out.len() + 2 > max_symbols {
+            return Err(Error::InvalidCode("too many symbols"));
+        }
+
+        let entry = table[states[i]];
+        out.push(entry.symbol);
+        states[i] = entry.base as usize + reader.read_bits(entry.nb_bits) as usize;
+        if reader.overflowed() {
+            out.push(table[states[1 - i]].symbol);
+            break;
+        }
+    }
+
+    Ok(out)
+}
+
+/// Encodes symbols with two interleaved states so that decode() returns
+/// exactly these symbols. There must be at least two symbols and every
+/// symbol must have a count in norm.
+pub(crate) fn encode(data: &[u8], norm: &[i16], table_log: u8) -> Vec<u8> {
+    let symbols = spread(norm, table_log).expect("normalized counts fill the table");
+    let first = first_states(norm);
+    let size = 1u32 << table_log;
+
+    // the states of every symbol in order of their state numbers
+    let mut states_of: Vec<Vec<u32>> = vec![Vec::new(); norm.len()];
+    for (u, s) in symbols.iter().enumerate() {
+        states_of[*s as usize].push(u as u32);
+    }
+
+    // the last two symbols are decoded from the initial states, which are
+    // chosen to read at least one bit on their update so that the decoder
+    // stops there
+    let mut writer = BackwardBitWriter::new();
+    let n = data.len();
+    let mut states = [0u32; 2];
+    for i in (0..n).rev() {
+        let s = data[i] as usize;
+        let which = i % 2;
+        if i >= n - 2 {
+            states[which] = states_of[s][0];
+            continue;
+        }
+
+        let x = states[which] + size;
+        let c = first[s];
+        let mut nb_bits = 0;
+        while (x >> nb_bits) >= 2 * c {
+            nb_bits += 1;
+        }
+        writer.add_bits((x & ((1 << nb_bits) - 1)) as u64, nb_bits as u8);
+        states[which] = states_of[s][((x >> nb_bits) - c) as usize];
+    }
+
+    writer.add_bits(states[1] as u64, table_log);
+    writer.add_bits(states[0] as u64, table_log);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_roundtrip() {
+        let counts = [0u32, 40, 3, 0, 0, 0, 0, 9, 1, 1];
+        let norm = normalize(&counts, 6).unwrap();
+        assert_eq!(norm.iter().map(|n| *n as i32).sum::<i32>(), 64);
+
+        let mut out = Vec::new();
+        write_counts(&norm, 6, &mut out);
+        let (read, table_log, len) = read_counts(&out, 12, 6).unwrap();
+        assert_eq!((read, table_log, len), (norm, 6, out.len()));
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        let data: Vec<u8> = (0..200u32).map(|i| [1, 1, 1, 2, 1, 7, 1, 2, 9, 8][(i * 7 % 10) as usize]).collect();
+        let mut counts = [0u32; 10];
+        for s in &data {
+            counts[*s as usize] += 1;
+        }
+        let norm = normalize(&counts, 6).unwrap();
+
+        for n in &[2, 3, 4, 5, 199, 200] {
+            let encoded = encode(&data[..*n], &norm, 6);
+            assert_eq!(decode(&encoded, &norm, 6, 255).unwrap(), &data[..*n]);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::io::{self, Read, Write};
+
+use crate::code_book::MAX_CODE_LEN;
+use crate::error::{Error, Result};
+use crate::freq_tree::FreqTreeNode;
+use crate::huffman_decoder::DecodeLimits;
+use crate::seekable_reader::SyncPoint;
+
+/// Flag set when a sync point index follows the serialized ftree.
+pub const FLAG_SYNC_INDEX: u8 = 0x01;
+
+/// Flag set when a chunk index follows the sync point index.
+pub const FLAG_CHUNK_INDEX: u8 = 0x02;
+
+/// All flags understood by this version of the crate.
+const KNOWN_FLAGS: u8 = FLAG_SYNC_INDEX | FLAG_CHUNK_INDEX;
+
+/// The first header word stores the size of the serialized ftree in its low
+/// 24 bits and the format flags in its high byte.
+const TREE_SIZE_MASK: u32 = 0x00ff_ffff;
+
+/// The header placed in front of the huffman coded data. Its layout is:
+///
+/// * `u32` little endian: ftree size in bytes (low 24 bits) and flags (high 8 bits)
+/// * `u64` little endian: size of the original data
+/// * the serialized ftree
+/// * if `FLAG_SYNC_INDEX` is set: a `u32` little endian count followed by that
+///   many pairs of `u64` little endian uncompressed offsets and bit offsets
+/// * if `FLAG_CHUNK_INDEX` is set: the start of every chunk added to the
+///   encoder, in the same layout as the sync point index
+pub struct Header {
+    pub flags: u8,
+    pub orig_size: u64,
+    pub ftree: FreqTreeNode,
+    pub sync_points: Vec<SyncPoint>,
+    pub chunk_starts: Vec<SyncPoint>,
+}
+
+impl Header {
+    /// Writes the serialized header to out
+    pub fn write_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        let ftree_serialized = self.ftree.encode();
+        let word = (ftree_serialized.len() as u32 & TREE_SIZE_MASK) | ((self.flags as u32) << 24);
+
+        out.write_all(&word.to_le_bytes())?;
+        out.write_all(&self.orig_size.to_le_bytes())?;
+        out.write_all(&ftree_serialized)?;
+
+        if self.flags & FLAG_SYNC_INDEX != 0 {
+            write_points(out, &self.sync_points)?;
+        }
+        if self.flags & FLAG_CHUNK_INDEX != 0 {
+            write_points(out, &self.chunk_starts)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of bytes write_to() writes
+    pub fn encoded_len(&self) -> usize {
+        let mut len = 12 + self.ftree.serialized_len();
+        if self.flags & FLAG_SYNC_INDEX != 0 {
+            len += 4 + 16 * self.sync_points.len();
+        }
+        if self.flags & FLAG_CHUNK_INDEX != 0 {
+            len += 4 + 16 * self.chunk_starts.len();
+        }
+        len
+    }
+
+    /// Returns the number of bytes of the header starting at buf that are
+    /// known to be needed: the length of the whole header once buf holds all
+    /// the fields it depends on, or else the length up to the next of those
+    /// fields. Fails with Error::LimitExceeded as soon as the ftree or the
+    /// whole header is known to exceed the limits. The header is not
+    /// otherwise validated.
+    pub fn needed_len(buf: &[u8], limits: &DecodeLimits) -> Result<usize> {
+        let word = match prefix_u32(buf, 0) {
+            Some(word) => word,
+            None => return Ok(4),
+        };
+        if (word & TREE_SIZE_MASK) as usize > max_tree_len(limits.max_tree_nodes) {
+            return Err(Error::LimitExceeded("tree nodes"));
+        }
+
+        let flags = (word >> 24) as u8;
+        let mut len = 12 + (word & TREE_SIZE_MASK) as usize;
+        for flag in &[FLAG_SYNC_INDEX, FLAG_CHUNK_INDEX] {
+            if flags & flag != 0 {
+                let count = match prefix_u32(buf, len) {
+                    Some(count) => count as usize,
+                    None => return Ok(len + 4),
+                };
+                len = len.saturating_add(count.saturating_mul(16)).saturating_add(4);
+                if len > limits.max_header_len {
+                    return Err(Error::LimitExceeded("header size"));
+                }
+            }
+        }
+        if len > limits.max_header_len {
+            return Err(Error::LimitExceeded("header size"));
+        }
+
+        Ok(len)
+    }
+
+    /// Reads a header from the reader, leaving the reader positioned at the
+    /// start of the huffman coded data. The ftree is held to the node limit
+    /// of limits.
+    pub fn read_from(reader: &mut dyn Read, limits: &DecodeLimits) -> Result<Header> {
+        let word = read_u32(reader)?;
+        let flags = read_flags(word)?;
+        let orig_size = read_u64(reader)?;
+
+        // the size is not trusted to allocate the buffer up front
+        let ftree_size = (word & TREE_SIZE_MASK) as usize;
+        let mut ftree_serialized = Vec::new();
+        reader.take(ftree_size as u64).read_to_end(&mut ftree_serialized)?;
+        if ftree_serialized.len() < ftree_size {
+            return Err(Error::UnexpectedEof);
+        }
+        let ftree = FreqTreeNode::decode_with_limit(&ftree_serialized, limits.max_tree_nodes)?;
+
+        read_indexes(reader, flags, orig_size, ftree)
+    }
+
+    /// Parses the header at the start of buf like read_from(), but decodes
+    /// the ftree in place. Returns the header and the offset of the huffman
+    /// coded data in buf.
+    pub fn parse(buf: &[u8], limits: &DecodeLimits) -> Result<(Header, usize)> {
+        let word = prefix_u32(buf, 0).ok_or(Error::UnexpectedEof)?;
+        let flags = read_flags(word)?;
+        let mut input = buf.get(4..).ok_or(Error::UnexpectedEof)?;
+        let orig_size = read_u64(&mut input)?;
+
+        let ftree_size = (word & TREE_SIZE_MASK) as usize;
+        let ftree_serialized = input.get(..ftree_size).ok_or(Error::UnexpectedEof)?;
+        let ftree = FreqTreeNode::decode_with_limit(ftree_serialized, limits.max_tree_nodes)?;
+        input = &input[ftree_size..];
+
+        let header = read_indexes(&mut input, flags, orig_size, ftree)?;
+        Ok((header, buf.len() - input.len()))
+    }
+}
+
+/// Returns the size of the largest serialized ftree with at most max_nodes
+/// nodes: every leaf takes 9 bits and every other node 1 bit.
+fn max_tree_len(max_nodes: usize) -> usize {
+    let leaves = max_nodes.saturating_add(1) / 2;
+    (10 * leaves.min(256)).saturating_sub(1).div_ceil(8)
+}
+
+fn read_flags(word: u32) -> Result<u8> {
+    let flags = (word >> 24) as u8;
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(Error::InvalidHeader("unknown format flags"));
+    }
+    Ok(flags)
+}
+
+/// Reads the indexes that follow the ftree and completes the header.
+fn read_indexes(reader: &mut dyn Read, flags: u8, orig_size: u64, ftree: FreqTreeNode) -> Result<Header> {
+    let mut sync_points = Vec::new();
+    if flags & FLAG_SYNC_INDEX != 0 {
+        sync_points = read_points(reader, orig_size)?;
+        if sync_points.windows(2).any(|w| w[0].offset == w[1].offset) {
+            return Err(Error::InvalidHeader("duplicate sync point"));
+        }
+    }
+
+    let mut chunk_starts = Vec::new();
+    if flags & FLAG_CHUNK_INDEX != 0 {
+        chunk_starts = read_points(reader, orig_size)?;
+        if chunk_starts.first().is_some_and(|p| p.offset != 0 || p.bit_offset != 0) {
+            return Err(Error::InvalidHeader("first chunk does not start at 0"));
+        }
+        if chunk_starts.is_empty() && orig_size > 0 {
+            return Err(Error::InvalidHeader("data without a chunk"));
+        }
+    }
+
+    Ok(Header {
+        flags,
+        orig_size,
+        ftree,
+        sync_points,
+        chunk_starts,
+    })
+}
+
+fn prefix_u32(buf: &[u8], at: usize) -> Option<u32> {
+    let bytes = buf.get(at..at + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn write_points(out: &mut dyn Write, points: &[SyncPoint]) -> io::Result<()> {
+    out.write_all(&(points.len() as u32).to_le_bytes())?;
+    for point in points {
+        out.write_all(&point.offset.to_le_bytes())?;
+        out.write_all(&point.bit_offset.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads an index of points, which must be in order and within the data. No
+/// byte takes more than MAX_CODE_LEN bits, which bounds the bit offsets.
+fn read_points(reader: &mut dyn Read, orig_size: u64) -> Result<Vec<SyncPoint>> {
+    let count = read_u32(reader)?;
+    let mut points: Vec<SyncPoint> = Vec::new();
+
+    for _ in 0..count {
+        let offset = read_u64(reader)?;
+        let bit_offset = read_u64(reader)?;
+        if offset > orig_size || bit_offset > orig_size.saturating_mul(MAX_CODE_LEN as u64) {
+            return Err(Error::InvalidHeader("index entry past end of data"));
+        }
+        if points.last().is_some_and(|p| p.offset > offset || p.bit_offset > bit_offset) {
+            return Err(Error::InvalidHeader("index entries out of order"));
+        }
+        points.push(SyncPoint { offset, bit_offset });
+    }
+
+    Ok(points)
+}
+
+/// Reads the `u64` little endian size of the original data that starts the
+/// formats besides the one of the HuffmanEncoder, and checks it against the
+/// output and ratio limits for an input of buf.len() bytes.
+pub(crate) fn read_orig_size(buf: &[u8], limits: &DecodeLimits) -> Result<u64> {
+    let mut input = buf;
+    let orig_size = read_u64(&mut input)?;
+    limits.check_output(orig_size, buf.len())?;
+    Ok(orig_size)
+}
+
+fn read_u32(reader: &mut dyn Read) -> Result<u32> {
+    let mut val_array = [0u8; std::mem::size_of::<u32>()];
+    reader.read_exact(&mut val_array)?;
+    Ok(u32::from_le_bytes(val_array))
+}
+
+fn read_u64(reader: &mut dyn Read) -> Result<u64> {
+    let mut val_array = [0u8; std::mem::size_of::<u64>()];
+    reader.read_exact(&mut val_array)?;
+    Ok(u64::from_le_bytes(val_array))
+}
@@ -0,0 +1,162 @@
+//! Huffman coding of HPACK string literals with the static code of RFC 7541
+//! Appendix B, which QPACK (RFC 9204) uses as well.
+//!
+//! The code covers the 256 byte values and an end of string symbol. Coded
+//! strings are padded to the next byte boundary with the most significant
+//! bits of the end of string code, which are all ones; the end of string
+//! symbol itself never appears in a string.
+
+use std::io::Write;
+use std::sync::OnceLock;
+
+use crate::canonical::{CanonicalCode, PartialCode};
+use crate::error::{Error, Result};
+
+use bitstream::{BitWriter, ReadBits, SliceBitReader};
+
+/// The symbol of the end of string code.
+const EOS_SYMBOL: usize = 256;
+
+/// The length of the code of every symbol. The code is canonical, so the
+/// lengths are all that is needed to rebuild it.
+const CODE_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28,
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+     6, 10, 10, 12, 13,  6,  8, 11, 10, 10,  8, 11,  8,  6,  6,  6,
+     5,  5,  5,  6,  6,  6,  6,  6,  6,  6,  7,  8, 15,  6, 12, 10,
+    13,  6,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,
+     7,  7,  7,  7,  7,  7,  7,  7,  8,  7,  8, 13, 19, 13, 14,  6,
+    15,  5,  6,  5,  6,  5,  6,  6,  6,  5,  7,  7,  6,  6,  6,  5,
+     6,  7,  6,  5,  5,  6,  7,  7,  7,  7,  7, 15, 11, 14, 13, 28,
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24,
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23,
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25,
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27,
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+    30,
+];
+
+/// Returns the static HPACK code.
+pub fn code() -> &'static CanonicalCode {
+    static CODE: OnceLock<CanonicalCode> = OnceLock::new();
+    CODE.get_or_init(|| CanonicalCode::from_lengths(&CODE_LENGTHS).expect("the HPACK code is valid"))
+}
+
+/// Returns the number of bytes encode() produces for data, so that callers
+/// can send the string uncoded when coding does not make it shorter.
+pub fn encoded_len(data: &[u8]) -> usize {
+    let bits: usize = data.iter().map(|b| CODE_LENGTHS[*b as usize] as usize).sum();
+    bits.div_ceil(8)
+}
+
+/// Huffman codes a string literal.
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(encoded_len(data));
+    encode_into(data, &mut out);
+    out
+}
+
+/// Huffman codes a string literal and appends the result to out.
+pub fn encode_into(data: &[u8], out: &mut Vec<u8>) {
+    let code = code();
+    let mut bit_writer = BitWriter::with_writer(out);
+    let mut bits: usize = 0;
+    for byte in data {
+        code.write_symbol(&mut bit_writer, *byte as usize).unwrap();
+        bits += CODE_LENGTHS[*byte as usize] as usize;
+    }
+
+    // the padding is the start of the end of string code
+    let padding = (8 - bits % 8) % 8;
+    bit_writer.add_bits(0xff, padding as u8).unwrap();
+    bit_writer.flush().unwrap();
+}
+
+/// Decodes a Huffman coded string literal. Fails with Error::InvalidCode if
+/// the string contains the end of string symbol, or if the padding is longer
+/// than 7 bits or not all ones.
+pub fn decode(buf: &[u8]) -> Result<Vec<u8>> {
+    let code = code();
+    let mut out = Vec::with_capacity(buf.len() * 8 / 5);
+    let mut reader = SliceBitReader::new(buf);
+    let mut partial = PartialCode::default();
+
+    while let Some(bit) = reader.get_bit() {
+        match code.next_bit(&mut partial, bit)? {
+            Some(EOS_SYMBOL) => return Err(Error::InvalidCode("end of string symbol in string")),
+            Some(symbol) => out.push(symbol as u8),
+            None => (),
+        }
+    }
+
+    if partial.len() > 7 {
+        return Err(Error::InvalidCode("padding longer than 7 bits"));
+    }
+    if partial.bits() != (1 << partial.len()) - 1 {
+        return Err(Error::InvalidCode("padding is not a prefix of the end of string code"));
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_rfc_vectors() {
+        // the Huffman coded strings of RFC 7541 Appendix C.4 and C.6
+        let vectors = [
+            ("www.example.com", "f1e3 c2e5 f23a 6ba0 ab90 f4ff"),
+            ("no-cache", "a8eb 1064 9cbf"),
+            ("custom-key", "25a8 49e9 5ba9 7d7f"),
+            ("custom-value", "25a8 49e9 5bb8 e8b4 bf"),
+            ("302", "6402"),
+            ("307", "640e ff"),
+            ("private", "aec3 771a 4b"),
+            ("Mon, 21 Oct 2013 20:13:21 GMT", "d07a be94 1054 d444 a820 0595 040b 8166 e082 a62d 1bff"),
+            ("Mon, 21 Oct 2013 20:13:22 GMT", "d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff"),
+            ("https://www.example.com", "9d29 ad17 1863 c78f 0b97 c8e9 ae82 ae43 d3"),
+            ("gzip", "9bd9 ab"),
+            ("foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1",
+                "94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f 9587 \
+                 3160 65c0 03ed 4ee5 b106 3d50 07"),
+        ];
+
+        for (plain, coded) in vectors.iter() {
+            let coded = hex(coded);
+            assert_eq!(encode(plain.as_bytes()), coded, "encoding {:?}", plain);
+            assert_eq!(encoded_len(plain.as_bytes()), coded.len());
+            assert_eq!(decode(&coded).unwrap(), plain.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_code() {
+        assert_eq!(code().code(b' ' as usize).unwrap().to_string(), "010100");
+        assert_eq!(code().code(EOS_SYMBOL).unwrap().bits, 0x3fff_ffff);
+
+        let all: Vec<u8> = (0..=255u8).collect();
+        assert_eq!(decode(&encode(&all)).unwrap(), all);
+        assert!(decode(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_padding() {
+        // 'a' is 00011, followed by three ones of padding
+        assert_eq!(decode(&[0x1f]).unwrap(), b"a");
+        assert!(matches!(decode(&[0x1e]), Err(Error::InvalidCode(_))));
+        assert!(matches!(decode(&[0x1f, 0xff]), Err(Error::InvalidCode(_))));
+
+        // the end of string code followed by two bits of padding
+        assert!(matches!(decode(&[0xff, 0xff, 0xff, 0xff]), Err(Error::InvalidCode(_))));
+    }
+}
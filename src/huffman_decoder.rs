@@ -1,8 +1,70 @@
+use std::cmp::min;
 use std::vec::Vec;
 
-use crate::freq_tree::*;
+use crate::code_book::CodeBook;
+use crate::error::{Error, Result};
+use crate::freq_tree::FreqTreeNode;
+use crate::header::{Header, FLAG_CHUNK_INDEX};
+use crate::seekable_reader::SyncPoint;
+
+use bitstream::SliceBitReader;
+
+/// Limits that protect the decoder from inputs crafted to use up memory, such
+/// as a small input claiming a huge original size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// The largest original size that will be decoded.
+    pub max_output: u64,
+    /// The largest allowed ratio of original size to encoded size. Every byte
+    /// has a code of at least one bit, so data produced by the HuffmanEncoder
+    /// never has a ratio above 8.
+    pub max_ratio: u64,
+    /// The largest number of nodes allowed in the huffman tree. A tree over all
+    /// 256 byte values has 511 nodes.
+    pub max_tree_nodes: usize,
+    /// The largest header, including the ftree and the indexes, that the
+    /// PushDecoder buffers before it starts decoding. The other decoders are
+    /// handed the whole header up front and do not need this limit.
+    pub max_header_len: usize,
+}
+
+impl DecodeLimits {
+    /// Limits that never stop decoding.
+    pub fn unlimited() -> DecodeLimits {
+        DecodeLimits {
+            max_output: u64::MAX,
+            max_ratio: u64::MAX,
+            max_tree_nodes: usize::MAX,
+            max_header_len: usize::MAX,
+        }
+    }
+
+    /// Checks the original size claimed by a header against the output and
+    /// ratio limits.
+    pub(crate) fn check_output(&self, orig_size: u64, encoded_size: usize) -> Result<()> {
+        if orig_size > self.max_output {
+            return Err(Error::LimitExceeded("output size"));
+        }
+        if orig_size > self.max_ratio.saturating_mul(encoded_size as u64) {
+            return Err(Error::LimitExceeded("compression ratio"));
+        }
+        Ok(())
+    }
+}
 
-use bitstream::{VecStream, BitReader};
+impl Default for DecodeLimits {
+    /// No limit on the output size, a ratio limit of 8 and a tree node limit
+    /// of 511, which accepts everything the HuffmanEncoder produces, and a
+    /// header limit of 16 MiB, which holds indexes of about a million entries.
+    fn default() -> DecodeLimits {
+        DecodeLimits {
+            max_output: u64::MAX,
+            max_ratio: 8,
+            max_tree_nodes: 511,
+            max_header_len: 16 << 20,
+        }
+    }
+}
 
 /// Huffman Decoder struct, currently only used as placeholder struct
 pub struct HuffmanDecoder {
@@ -10,64 +72,143 @@ pub struct HuffmanDecoder {
 }
 
 impl HuffmanDecoder {
-    /// Directly decodes a buffer encoded with the HuffmanEncoder. 
-    pub fn decode(buf: Vec<u8>) -> Vec<u8> {
-        let (ftree, mut original_size, encoded) = Self::parse_header(buf);
-        let mut vs = VecStream::from_vec(encoded);
-        let mut breader = BitReader::with_reader(&mut vs);
-
-        let mut ret = Vec::<u8>::new();
-
-        let mut at = &ftree;
-
-        while original_size > 0 {
-            let bit = breader.get_bit();
-            if bit.is_none() {
-                break;
-            }
-
-            if let FreqNodeData::Composit(c) = &at.data {
-                match bit.unwrap() {
-                    false => at = c.left.as_ref(),
-                    true => at = c.right.as_ref()
-                }
-            }
-
-            if let FreqNodeData::Value(v) = &at.data {
-                ret.push(v.byte_val);
-                at = &ftree;
-                original_size -= 1;
-            }
+    /// Directly decodes a buffer encoded with the HuffmanEncoder using the
+    /// default DecodeLimits.
+    pub fn decode(buf: Vec<u8>) -> Result<Vec<u8>> {
+        Self::decode_slice(&buf)
+    }
+
+    /// Decodes a borrowed buffer encoded with the HuffmanEncoder using the
+    /// default DecodeLimits. The coded data is read in place.
+    pub fn decode_slice(buf: &[u8]) -> Result<Vec<u8>> {
+        Self::decode_with_limits(buf, &DecodeLimits::default())
+    }
+
+    /// Decodes a borrowed buffer encoded with the HuffmanEncoder into out and
+    /// returns the number of bytes written, using the default DecodeLimits.
+    /// Fails with Error::BufferTooSmall if out cannot hold the original data.
+    /// Nothing is allocated for the coded data or the output.
+    pub fn decode_into(buf: &[u8], out: &mut [u8]) -> Result<usize> {
+        let limits = DecodeLimits::default();
+        let (header, data_start) = Header::parse(buf, &limits)?;
+        limits.check_output(header.orig_size, buf.len())?;
+        let input = &buf[data_start..];
+        if header.orig_size > out.len() as u64 {
+            return Err(Error::BufferTooSmall { needed: header.orig_size, available: out.len() });
+        }
+
+        let out = &mut out[..header.orig_size as usize];
+        let mut breader = SliceBitReader::new(input);
 
+        for slot in out.iter_mut() {
+            let (byte, _) = header.ftree.read_symbol(&mut breader).ok_or(Error::UnexpectedEof)?;
+            *slot = byte;
         }
 
-        ret
+        Ok(out.len())
     }
 
-    fn parse_header(buf: Vec<u8>) -> (FreqTreeNode, u64, Vec<u8>) {
-        let (header_size, orig_header) = Self::parse_32le_val(buf);
-        let (orig_size, mut header) = Self::parse_64le_val(orig_header);
+    /// Decodes a buffer encoded with the HuffmanEncoder, failing with
+    /// Error::LimitExceeded if the data would exceed any of the limits.
+    pub fn decode_with_limits(buf: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>> {
+        let (header, data_start) = Header::parse(buf, limits)?;
+        limits.check_output(header.orig_size, buf.len())?;
+        let input = &buf[data_start..];
 
-        let remaining = header.split_off(header_size as usize);
+        // every byte takes at least one bit, never reserve more than that
+        let capacity = min(header.orig_size, input.len() as u64 * 8) as usize;
+        let mut ret = Vec::<u8>::with_capacity(capacity);
+        let mut breader = SliceBitReader::new(input);
+        Self::decode_symbols(&header.ftree, &mut breader, header.orig_size, &mut ret)?;
 
-        (FreqTreeNode::decode(&header), orig_size, remaining)
+        Ok(ret)
+    }
+
+    /// Decodes a buffer encoded with the HuffmanEncoder into the chunks that
+    /// were added to the encoder, using the default DecodeLimits. Data encoded
+    /// without set_record_chunks() is returned as a single chunk.
+    pub fn decode_chunks(buf: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let limits = DecodeLimits::default();
+        let (header, data_start) = Header::parse(buf, &limits)?;
+        limits.check_output(header.orig_size, buf.len())?;
+        let input = &buf[data_start..];
+
+        let mut breader = SliceBitReader::new(input);
+        let mut chunks = Vec::new();
+        for (_, len) in Self::chunk_bounds(&header) {
+            let mut chunk = Vec::with_capacity(len as usize);
+            Self::decode_symbols(&header.ftree, &mut breader, len, &mut chunk)?;
+            chunks.push(chunk);
+        }
+
+        Ok(chunks)
+    }
+
+    /// Decodes only the chunk with the given index, starting at the position
+    /// recorded for it instead of decoding all chunks before it. Data encoded
+    /// without set_record_chunks() has a single chunk.
+    pub fn decode_chunk(buf: &[u8], index: usize) -> Result<Vec<u8>> {
+        let limits = DecodeLimits::default();
+        let (header, data_start) = Header::parse(buf, &limits)?;
+        let input = &buf[data_start..];
+
+        let bounds = Self::chunk_bounds(&header);
+        let (start, len) = *bounds.get(index)
+            .ok_or(Error::NoSuchChunk { index, count: bounds.len() })?;
+        limits.check_output(len, buf.len())?;
+
+        let mut breader = SliceBitReader::new(input);
+        if start.bit_offset > input.len() as u64 * 8 {
+            return Err(Error::UnexpectedEof);
+        }
+        breader.set_bit_position(start.bit_offset as usize);
+
+        let mut chunk = Vec::with_capacity(len as usize);
+        Self::decode_symbols(&header.ftree, &mut breader, len, &mut chunk)?;
+        Ok(chunk)
+    }
+
+    /// Returns the number of chunks in a buffer encoded with the
+    /// HuffmanEncoder by reading only its header.
+    pub fn chunk_count(buf: &[u8]) -> Result<usize> {
+        let (header, _) = Header::parse(buf, &DecodeLimits::default())?;
+        Ok(Self::chunk_bounds(&header).len())
+    }
+
+    /// Returns the start and length of every chunk.
+    fn chunk_bounds(header: &Header) -> Vec<(SyncPoint, u64)> {
+        if header.flags & FLAG_CHUNK_INDEX == 0 {
+            return vec![(SyncPoint { offset: 0, bit_offset: 0 }, header.orig_size)];
+        }
+
+        let starts = &header.chunk_starts;
+        starts.iter().enumerate()
+            .map(|(i, start)| {
+                let end = starts.get(i + 1).map_or(header.orig_size, |next| next.offset);
+                (*start, end - start.offset)
+            })
+            .collect()
+    }
+
+    fn decode_symbols(ftree: &FreqTreeNode, reader: &mut SliceBitReader, count: u64,
+                      out: &mut Vec<u8>) -> Result<()> {
+        for _ in 0..count {
+            let (byte, _) = ftree.read_symbol(reader).ok_or(Error::UnexpectedEof)?;
+            out.push(byte);
+        }
+        Ok(())
     }
 
-    fn parse_32le_val(mut buf: Vec<u8>) -> (u32, Vec<u8>) {
-        let remaining = buf.split_off(std::mem::size_of::<u32>());
-        let mut val_array = [0u8; std::mem::size_of::<u32>()];
-        let val_bytes = &buf[..val_array.len()];
-        val_array.copy_from_slice(val_bytes);
-        let val = u32::from_le_bytes(val_array);
-        (val, remaining)
+    /// Reads only the header of a buffer encoded with the HuffmanEncoder and
+    /// returns the huffman tree stored in it. Weights are not stored in the
+    /// header so all weights of the returned tree are 0.
+    pub fn read_tree(buf: &[u8]) -> Result<FreqTreeNode> {
+        Ok(Header::parse(buf, &DecodeLimits::default())?.0.ftree)
     }
 
-    fn parse_64le_val(mut buf: Vec<u8>) -> (u64, Vec<u8>) {
-        let remaining = buf.split_off(std::mem::size_of::<u64>());
-        let mut val_array = [0u8; std::mem::size_of::<u64>()];
-        let val_bytes = &buf[..val_array.len()];
-        val_array.copy_from_slice(val_bytes);
-        let val = u64::from_le_bytes(val_array);
-        (val, remaining)
+    /// Reads only the header of a buffer encoded with the HuffmanEncoder and
+    /// returns the code book used for the data.
+    pub fn read_code_book(buf: &[u8]) -> Result<CodeBook> {
+        CodeBook::from_tree(&Self::read_tree(buf)?)
     }
 }
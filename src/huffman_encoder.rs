@@ -1,139 +1,286 @@
 use std::vec::Vec;
-use std::collections::{LinkedList, HashMap, BinaryHeap, VecDeque};
-use std::io::Write;
+use std::borrow::Cow;
+use std::collections::LinkedList;
+use std::io::{self, Write};
+use std::iter::FromIterator;
 
+use crate::code_book::{CodeBook, MAX_CODE_LEN};
+use crate::error::{Error, Result};
+use crate::freq_table::FrequencyTable;
 use crate::freq_tree::*;
 
-use bitstream::{VecStream, BitWriter};
+use crate::header::{Header, FLAG_SYNC_INDEX, FLAG_CHUNK_INDEX};
+use crate::seekable_reader::SyncPoint;
+
+use bitstream::BitWriter;
 
 /// Encoder struct. Allows a user to add chunks a peice at a time and
-/// encode all at once.
-pub struct HuffmanEncoder {
-    byte_counts: HashMap<u8, u64>,
-    chunks: LinkedList<Vec<u8>>,
+/// encode all at once. Chunks are either copied into the encoder with
+/// add_chunk() or borrowed for the lifetime 'a with add_chunk_ref().
+pub struct HuffmanEncoder<'a> {
+    byte_counts: FrequencyTable,
+    chunks: LinkedList<Cow<'a, [u8]>>,
+    sync_interval: u64,
+    record_chunks: bool,
+    fixed_table: Option<FrequencyTable>,
+}
+
+impl<'a> Default for HuffmanEncoder<'a> {
+    fn default() -> HuffmanEncoder<'a> {
+        HuffmanEncoder::new()
+    }
+}
+
+impl<'a> Write for HuffmanEncoder<'a> {
+    /// Adds buf to the encoder as a chunk. Never fails.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.add_chunk(buf);
+        Ok(buf.len())
+    }
+
+    /// The flush() function is a no-op, data is only encoded by encode().
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> Extend<u8> for HuffmanEncoder<'a> {
+    /// Adds all bytes of the iterator to the encoder as a single chunk.
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        let chunk: Vec<u8> = iter.into_iter().collect();
+        self.add_chunk(&chunk);
+    }
+}
+
+impl<'a, 'b> Extend<&'b u8> for HuffmanEncoder<'a> {
+    /// Adds all bytes of the iterator to the encoder as a single chunk.
+    fn extend<I: IntoIterator<Item = &'b u8>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().cloned());
+    }
 }
 
-impl HuffmanEncoder {
+impl<'a> Extend<&'a [u8]> for HuffmanEncoder<'a> {
+    /// Adds every slice of the iterator to the encoder as a borrowed chunk.
+    fn extend<I: IntoIterator<Item = &'a [u8]>>(&mut self, iter: I) {
+        for chunk in iter {
+            self.add_chunk_ref(chunk);
+        }
+    }
+}
+
+impl<'a> FromIterator<u8> for HuffmanEncoder<'a> {
+    /// Creates an encoder holding all bytes of the iterator as a single chunk.
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> HuffmanEncoder<'a> {
+        let mut encoder = HuffmanEncoder::new();
+        encoder.extend(iter);
+        encoder
+    }
+}
+
+impl<'a> HuffmanEncoder<'a> {
     /// Create a new encoder struct with no data.
-    pub fn new() -> HuffmanEncoder {
+    pub fn new() -> HuffmanEncoder<'a> {
         HuffmanEncoder {
-            byte_counts: HashMap::new(),
+            byte_counts: FrequencyTable::new(),
             chunks: LinkedList::new(),
+            sync_interval: 0,
+            record_chunks: false,
+            fixed_table: None,
         }
     }
 
-    /// Add a chunk of data to the encoder
+    /// Create a new encoder that builds its huffman tree from the given table
+    /// instead of from the bytes added to it. Bytes which appear in the added
+    /// data but have a count of 0 in the table are given a count of 1 so that
+    /// every byte can still be encoded.
+    pub fn with_frequencies(table: FrequencyTable) -> HuffmanEncoder<'a> {
+        let mut encoder = HuffmanEncoder::new();
+        encoder.fixed_table = Some(table);
+        encoder
+    }
+
+    /// Returns the byte counts of all data added so far. This table can be
+    /// saved and used to create encoders for similar data later.
+    pub fn frequencies(&self) -> FrequencyTable {
+        self.byte_counts.clone()
+    }
+
+    /// Record a sync point every interval bytes of original data. Sync points
+    /// are stored in the header and allow a SeekableHuffmanReader to start
+    /// decoding close to any offset instead of at the beginning. An interval
+    /// of 0 (the default) disables the index.
+    pub fn set_sync_interval(&mut self, interval: u64) {
+        self.sync_interval = interval;
+    }
+
+    /// Record the length and position of every chunk in the header so that
+    /// the HuffmanDecoder can return the chunks separately with decode_chunks()
+    /// or decode a single chunk with decode_chunk(). Every call to add_chunk(),
+    /// write() or extend() adds one chunk. Disabled by default.
+    pub fn set_record_chunks(&mut self, record: bool) {
+        self.record_chunks = record;
+    }
+
+    /// Add a chunk of data to the encoder. The chunk is copied.
     pub fn add_chunk(&mut self, chunk: &[u8]) {
+        self.byte_counts.add_data(chunk);
+        self.chunks.push_back(Cow::Owned(chunk.to_vec()));
+    }
 
-        for c in chunk {
-            if self.byte_counts.contains_key(c) {
-                *self.byte_counts.get_mut(c).unwrap() += 1;
-            } else {
-                self.byte_counts.insert(*c, 1);
-            }
-        }
+    /// Add a chunk of data to the encoder without copying it. The chunk is
+    /// borrowed until the encoder is dropped and is encoded directly from
+    /// where it lives.
+    pub fn add_chunk_ref(&mut self, chunk: &'a [u8]) {
+        self.byte_counts.add_data(chunk);
+        self.chunks.push_back(Cow::Borrowed(chunk));
+    }
 
-        self.chunks.push_back(chunk.to_vec());
+    /// Returns the code book of the huffman tree that encode() would use for
+    /// the data added so far.
+    pub fn code_book(&self) -> CodeBook {
+        CodeBook::from_tree(&self.build_tree()).expect("built trees have valid codes")
     }
 
     /// Encode all chunks of data using the same huffman tree
     pub fn encode(self) -> Vec<u8> {
-        let ftree = self.build_freq_tree();
-        let encoding_map = self.build_encoding_map(&ftree);
-        let mut vs = VecStream::new();
-        let mut bit_writer = BitWriter::with_writer(&mut vs);
-        let mut orig_size: u64 = 0;
+        let mut encoded = Vec::new();
+        self.encode_into(&mut encoded);
+        encoded
+    }
 
-        for chunk in self.chunks {
-            orig_size += chunk.len() as u64;
-            for byte in chunk {
-                bit_writer.write(&encoding_map.get(&byte).unwrap()).unwrap();
-            }
+    /// Encode all chunks of data and append the result to out. The space
+    /// needed is reserved up front so out grows at most once.
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        let (header, code_book, data_len) = self.prepare();
+        out.reserve(header.encoded_len() + data_len);
+        self.write_encoded(&header, &code_book, out).unwrap();
+    }
+
+    /// Encode all chunks of data into the start of out and return the number
+    /// of bytes written. Fails with Error::BufferTooSmall, without writing
+    /// anything, if out is smaller than encoded_len().
+    pub fn encode_to_slice(&self, out: &mut [u8]) -> Result<usize> {
+        let (header, code_book, data_len) = self.prepare();
+        let len = header.encoded_len() + data_len;
+        if out.len() < len {
+            return Err(Error::BufferTooSmall { needed: len as u64, available: out.len() });
         }
 
-        drop(bit_writer);
+        let mut writer = &mut out[..len];
+        self.write_encoded(&header, &code_book, &mut writer)?;
+        Ok(len)
+    }
 
-        let mut ftree_serialized = ftree.encode();
-        let mut orig_header = orig_size.to_le_bytes().to_vec();
-        let mut header_size = (ftree_serialized.len() as u32).to_le_bytes().to_vec();
-        let mut huff_data = vs.into_vec();
+    /// Returns the exact number of bytes encode() produces for the data
+    /// added so far.
+    pub fn encoded_len(&self) -> usize {
+        let (header, _, data_len) = self.prepare();
+        header.encoded_len() + data_len
+    }
 
-        let mut encoded = Vec::<u8>::new();
-        encoded.append(&mut header_size);
-        encoded.append(&mut orig_header);
-        encoded.append(&mut ftree_serialized);
-        encoded.append(&mut huff_data);
-        encoded
+    /// Returns an upper bound of the encoded size of input_len bytes of data
+    /// with the settings of this encoder, to size buffers ahead of time. Rare
+    /// bytes may get codes longer than 8 bits, but a tree built from the data
+    /// itself never codes the whole data in more than 8 bits per byte, while
+    /// a tree from a fixed FrequencyTable may spend up to MAX_CODE_LEN bits on
+    /// every byte. When chunks are recorded the chunk index is included for
+    /// the chunks added so far plus one more chunk if input_len exceeds the
+    /// data added so far.
+    pub fn max_encoded_len(&self, input_len: usize) -> usize {
+        // header words, the largest tree of 256 leaves and the sync index
+        let mut len: usize = 12 + 320;
+        if let Some(points) = (input_len.saturating_sub(1) as u64).checked_div(self.sync_interval) {
+            len = len.saturating_add((points as usize).saturating_mul(16).saturating_add(4));
+        }
+        if self.record_chunks {
+            let added: usize = self.chunks.iter().map(|c| c.len()).sum();
+            let pending = (input_len > added) as usize;
+            len = len.saturating_add((self.chunks.len() + pending).saturating_mul(16).saturating_add(4));
+        }
+
+        let bits_per_byte = if self.fixed_table.is_some() { MAX_CODE_LEN as usize } else { 8 };
+        len.saturating_add(input_len.saturating_mul(bits_per_byte.div_ceil(8)))
     }
 
-    fn build_freq_tree(&self) -> Box<FreqTreeNode> {
-        let mut heap: BinaryHeap<FreqTreeNode> = BinaryHeap::new();
+    /// Builds the header and code book for the data added so far, and
+    /// computes the size of the coded data in bytes.
+    fn prepare(&self) -> (Header, CodeBook, usize) {
+        let ftree = self.build_tree();
+        let code_book = CodeBook::from_tree(&ftree).expect("built trees have valid codes");
 
-        // add all of the values to the heap as Value nodes
-        for (k, v) in &self.byte_counts {
-            heap.push(FreqTreeNode {data : FreqNodeData::Value( FreqTreeVal {byte_val: *k, occures: *v} ) });
+        let mut orig_size: u64 = 0;
+        let mut bit_count: u64 = 0;
+        for (byte, count) in self.byte_counts.counts().iter().enumerate() {
+            if *count > 0 {
+                orig_size += count;
+                bit_count += count * code_book.code(byte as u8).unwrap().len as u64;
+            }
         }
 
-        while heap.len() > 1 {
-            let left: FreqTreeNode = heap.pop().unwrap();
-            let right: FreqTreeNode = heap.pop().unwrap();
+        let mut sync_points = Vec::new();
+        let mut chunk_starts = Vec::new();
+        if self.sync_interval > 0 || self.record_chunks {
+            let mut offset: u64 = 0;
+            let mut bit_offset: u64 = 0;
+            for chunk in &self.chunks {
+                if self.record_chunks {
+                    chunk_starts.push(SyncPoint { offset, bit_offset });
+                }
 
-            let total = left.get_weight() + right.get_weight();
+                for byte in chunk.iter() {
+                    if self.sync_interval > 0 && offset > 0 && offset.is_multiple_of(self.sync_interval) {
+                        sync_points.push(SyncPoint { offset, bit_offset });
+                    }
+                    bit_offset += code_book.code(*byte).unwrap().len as u64;
+                    offset += 1;
+                }
+            }
+        }
 
-            let composit = FreqTreeNode { data : FreqNodeData::Composit( FreqTreeComposit {
-                occures: total,
-                left: Box::new(left),
-                right: Box::new(right),
-            })};
-            heap.push(composit);
+        let mut flags = 0;
+        if self.sync_interval > 0 {
+            flags |= FLAG_SYNC_INDEX;
+        }
+        if self.record_chunks {
+            flags |= FLAG_CHUNK_INDEX;
         }
 
-        Box::new(heap.pop().unwrap())
+        let header = Header {
+            flags,
+            orig_size,
+            ftree,
+            sync_points,
+            chunk_starts,
+        };
+
+        (header, code_book, bit_count.div_ceil(8) as usize)
     }
 
-    fn build_encoding_map(&self, ftree: &Box<FreqTreeNode>) -> HashMap<u8, Vec<u8>> {
-        struct QueueNode<'a> {
-            node: &'a Box<FreqTreeNode>,
-            bits: Vec<u8>,
+    fn write_encoded(&self, header: &Header, code_book: &CodeBook, out: &mut dyn Write) -> io::Result<()> {
+        header.write_to(out)?;
+
+        let mut bit_writer = BitWriter::with_writer(out);
+        for byte in self.chunks.iter().flat_map(|c| c.iter()) {
+            let code = code_book.code(*byte).unwrap();
+            bit_writer.add_bits(code.bits, code.len)?;
         }
-        let mut tqueue = VecDeque::<QueueNode>::new();
-        let mut encoding_map = HashMap::<u8, Vec<u8>>::new();
-
-        tqueue.push_back(QueueNode{
-            node: ftree,
-            bits: Vec::new()
-        });
-
-        loop {
-            let node = tqueue.pop_front();
-            match node {
-                Some(n) => {
-                    match &n.node.data {
-                        FreqNodeData::Composit(c) => {
-                            let mut left_vec = n.bits.clone();
-                            left_vec.push(0);
-                            tqueue.push_back(QueueNode {
-                                node: &c.left,
-                                bits: left_vec,
-                            });
-
-                            let mut right_vec = n.bits.clone();
-                            right_vec.push(1);
-                            tqueue.push_back(QueueNode {
-                                node: &c.right,
-                                bits: right_vec,
-                            });
-                        },
-                        FreqNodeData::Value(v) => {
-                            encoding_map.insert(v.byte_val, n.bits);
-                        }
+        bit_writer.flush()
+    }
+
+    fn build_tree(&self) -> FreqTreeNode {
+        let table = match &self.fixed_table {
+            Some(fixed) => {
+                let mut table = fixed.clone();
+                for byte in 0..=255u8 {
+                    if table.get(byte) == 0 && self.byte_counts.get(byte) > 0 {
+                        table.set(byte, 1);
                     }
-                },
-                None => break
-            }
-        }
+                }
+                table
+            },
+            None => self.frequencies(),
+        };
 
-        encoding_map
+        FreqTreeNode::from_frequencies(&table)
     }
 }
-
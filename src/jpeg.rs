@@ -0,0 +1,294 @@
+//! Huffman tables as JPEG stores them in its DHT segments: `BITS`, the number
+//! of codes of each length from 1 to 16 bits, followed by `HUFFVAL`, the
+//! symbols in order of their codes.
+//!
+//! Codes are assigned as in Annex C of the JPEG standard: in order of
+//! length and, within a length, in the order the symbols are listed. Tables
+//! built from frequencies follow Annex K and leave the code of all ones
+//! unused.
+
+use std::io::{self, Write};
+
+use crate::canonical::{CanonicalCode, code_lengths};
+use crate::code_book::Code;
+use crate::error::{Error, Result};
+use crate::freq_table::FrequencyTable;
+
+use bitstream::{BitWriter, ReadBits, SliceBitReader};
+
+/// The longest code JPEG allows.
+pub const MAX_JPEG_CODE_LEN: u32 = 16;
+
+/// The marker that starts a DHT segment.
+const DHT_MARKER: [u8; 2] = [0xff, 0xc4];
+
+/// A symbol with a count of 1 that takes the code of all ones while the
+/// lengths are computed, and is then left out.
+const RESERVED_SYMBOL: usize = 256;
+
+/// A huffman table over byte symbols in the form of a JPEG DHT table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JpegHuffmanTable {
+    bits: [u8; 16],
+    huffval: Vec<u8>,
+    /// the code over the positions in huffval
+    code: CanonicalCode,
+    /// the position of every symbol in huffval
+    positions: Vec<Option<usize>>,
+}
+
+impl JpegHuffmanTable {
+    /// Builds the table for the symbol counts of a FrequencyTable. Symbols
+    /// with a count of 0 get no code, so a table of only zeros has no codes at
+    /// all, no code is longer than 16 bits and the code of all ones is never
+    /// assigned.
+    pub fn from_frequencies(table: &FrequencyTable) -> JpegHuffmanTable {
+        let mut freqs = table.counts().to_vec();
+        freqs.push(1);
+        let mut lengths = code_lengths(&freqs, MAX_JPEG_CODE_LEN);
+
+        // with the reserved symbol alone, code_lengths pads the code with a
+        // symbol that has no count
+        for (len, freq) in lengths.iter_mut().zip(&freqs) {
+            if *freq == 0 {
+                *len = 0;
+            }
+        }
+
+        // the reserved symbol comes last among the longest codes, which is
+        // the code of all ones
+        let max_len = *lengths.iter().max().unwrap();
+        if lengths[RESERVED_SYMBOL] < max_len {
+            let deepest = lengths.iter().position(|l| *l == max_len).unwrap();
+            lengths.swap(deepest, RESERVED_SYMBOL);
+        }
+
+        let mut bits = [0u8; 16];
+        let mut huffval = Vec::new();
+        for len in 1..=MAX_JPEG_CODE_LEN as u8 {
+            for symbol in 0..=255u8 {
+                if lengths[symbol as usize] == len {
+                    huffval.push(symbol);
+                    bits[len as usize - 1] += 1;
+                }
+            }
+        }
+
+        Self::from_dht(&bits, &huffval).expect("computed lengths form a code")
+    }
+
+    /// Builds the table from the BITS and HUFFVAL lists of a DHT segment.
+    /// Fails with Error::InvalidCode if the number of symbols does not match
+    /// BITS, a symbol is listed twice or the lengths do not fit into a prefix
+    /// code.
+    pub fn from_dht(bits: &[u8; 16], huffval: &[u8]) -> Result<JpegHuffmanTable> {
+        let count: usize = bits.iter().map(|b| *b as usize).sum();
+        if count != huffval.len() {
+            return Err(Error::InvalidCode("BITS does not match the number of symbols"));
+        }
+
+        let mut positions = vec![None; 256];
+        for (pos, symbol) in huffval.iter().enumerate() {
+            if positions[*symbol as usize].replace(pos).is_some() {
+                return Err(Error::InvalidCode("symbol listed twice"));
+            }
+        }
+
+        let lengths: Vec<u8> = bits.iter().enumerate()
+            .flat_map(|(i, n)| std::iter::repeat_n(i as u8 + 1, *n as usize))
+            .collect();
+
+        Ok(JpegHuffmanTable {
+            bits: *bits,
+            huffval: huffval.to_vec(),
+            code: CanonicalCode::from_lengths(&lengths)?,
+            positions,
+        })
+    }
+
+    /// Returns the number of codes of each length from 1 to 16 bits.
+    pub fn bits(&self) -> &[u8; 16] {
+        &self.bits
+    }
+
+    /// Returns the symbols in order of their codes.
+    pub fn huffval(&self) -> &[u8] {
+        &self.huffval
+    }
+
+    /// Returns the code of a symbol, or None if the symbol has no code.
+    pub fn code(&self, symbol: u8) -> Option<Code> {
+        self.positions[symbol as usize].and_then(|pos| self.code.code(pos))
+    }
+
+    /// Returns a DHT marker segment holding this table with the given table
+    /// class (0 for DC, 1 for AC) and destination id.
+    pub fn dht_segment(&self, class: u8, id: u8) -> Vec<u8> {
+        let len = 2 + 1 + 16 + self.huffval.len();
+        let mut out = Vec::with_capacity(2 + len);
+        out.extend_from_slice(&DHT_MARKER);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+        out.push((class << 4) | (id & 0x0f));
+        out.extend_from_slice(&self.bits);
+        out.extend_from_slice(&self.huffval);
+        out
+    }
+
+    /// Reads all tables of a DHT marker segment, starting at its marker, and
+    /// returns them with their table class and destination id.
+    pub fn read_dht_segment(buf: &[u8]) -> Result<Vec<(u8, u8, JpegHuffmanTable)>> {
+        if buf.len() < 4 {
+            return Err(Error::UnexpectedEof);
+        }
+        if buf[..2] != DHT_MARKER {
+            return Err(Error::InvalidHeader("not a DHT segment"));
+        }
+
+        let len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        let mut rest = buf.get(4..2 + len).ok_or(Error::UnexpectedEof)?;
+        let mut tables = Vec::new();
+        while !rest.is_empty() {
+            if rest.len() < 17 {
+                return Err(Error::UnexpectedEof);
+            }
+            let mut bits = [0u8; 16];
+            bits.copy_from_slice(&rest[1..17]);
+            let count: usize = bits.iter().map(|b| *b as usize).sum();
+            let huffval = rest.get(17..17 + count).ok_or(Error::UnexpectedEof)?;
+
+            tables.push((rest[0] >> 4, rest[0] & 0x0f, Self::from_dht(&bits, huffval)?));
+            rest = &rest[17 + count..];
+        }
+
+        Ok(tables)
+    }
+
+    /// Writes the code of symbol to writer.
+    ///
+    /// Panics if the symbol has no code.
+    pub fn write_symbol(&self, writer: &mut BitWriter, symbol: u8) -> io::Result<()> {
+        let code = self.code(symbol).expect("symbol has no code");
+        writer.add_bits(code.bits, code.len)
+    }
+
+    /// Reads one code from reader and returns its symbol.
+    pub fn read_symbol<R: ReadBits + ?Sized>(&self, reader: &mut R) -> Result<u8> {
+        Ok(self.huffval[self.code.read_symbol(reader)?])
+    }
+
+    /// Codes a stream of symbols, padding the last byte with 1 bits as JPEG
+    /// does. Byte stuffing of the entropy coded segment is left to the
+    /// caller.
+    ///
+    /// Panics if a symbol has no code.
+    pub fn encode(&self, symbols: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut bit_writer = BitWriter::with_writer(&mut out);
+        let mut bit_count: usize = 0;
+        for symbol in symbols {
+            self.write_symbol(&mut bit_writer, *symbol).unwrap();
+            bit_count += self.code(*symbol).unwrap().len as usize;
+        }
+
+        let padding = (8 - bit_count % 8) % 8;
+        bit_writer.add_bits(0xff, padding as u8).unwrap();
+        bit_writer.flush().unwrap();
+        drop(bit_writer);
+        out
+    }
+
+    /// Decodes count symbols from a stream produced by encode().
+    pub fn decode(&self, buf: &[u8], count: usize) -> Result<Vec<u8>> {
+        let mut reader = SliceBitReader::new(buf);
+        (0..count).map(|_| self.read_symbol(&mut reader)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standard_dc_table() {
+        // the luminance DC table of Annex K.3
+        let bits = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+        let huffval: Vec<u8> = (0..12).collect();
+        let table = JpegHuffmanTable::from_dht(&bits, &huffval).unwrap();
+
+        let codes: Vec<String> = (0..12).map(|s| table.code(s).unwrap().to_string()).collect();
+        assert_eq!(codes, vec!["00", "010", "011", "100", "101", "110", "1110", "11110",
+            "111110", "1111110", "11111110", "111111110"]);
+
+        let segment = table.dht_segment(0, 0);
+        assert_eq!(&segment[..5], &[0xff, 0xc4, 0x00, 0x1f, 0x00]);
+        let tables = JpegHuffmanTable::read_dht_segment(&segment).unwrap();
+        assert_eq!(tables, vec![(0, 0, table)]);
+    }
+
+    #[test]
+    fn test_huffval_order() {
+        // codes of the same length follow the order of HUFFVAL, not the
+        // symbol values
+        let table = JpegHuffmanTable::from_dht(&[0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], b"zay").unwrap();
+        assert_eq!(table.code(b'z').unwrap().to_string(), "00");
+        assert_eq!(table.code(b'a').unwrap().to_string(), "01");
+        assert_eq!(table.code(b'y').unwrap().to_string(), "10");
+
+        let symbols = b"yazzay";
+        let coded = table.encode(symbols);
+        assert_eq!(coded, vec![0b1001_0000, 0b0110_1111]);
+        assert_eq!(table.decode(&coded, symbols.len()).unwrap(), symbols);
+    }
+
+    #[test]
+    fn test_from_frequencies() {
+        // fibonacci counts would need codes of up to 29 bits
+        let mut freqs = FrequencyTable::new();
+        let (mut a, mut b) = (1u64, 1u64);
+        for symbol in 0..30u8 {
+            freqs.set(symbol * 3, a);
+            let next = a + b;
+            a = b;
+            b = next;
+        }
+
+        let table = JpegHuffmanTable::from_frequencies(&freqs);
+        assert_eq!(table.huffval().len(), 30);
+        assert!(table.code(1).is_none());
+        assert!(table.bits()[15] > 0);
+
+        // no code is all ones
+        for symbol in table.huffval() {
+            let code = table.code(*symbol).unwrap();
+            assert_ne!(code.bits, (1 << code.len) - 1);
+        }
+
+        let symbols: Vec<u8> = (0..30u8).map(|s| s * 3).cycle().take(200).collect();
+        let coded = table.encode(&symbols);
+        assert_eq!(table.decode(&coded, symbols.len()).unwrap(), symbols);
+        assert_eq!(JpegHuffmanTable::from_dht(table.bits(), table.huffval()).unwrap(), table);
+    }
+
+    #[test]
+    fn test_from_empty_frequencies() {
+        let table = JpegHuffmanTable::from_frequencies(&FrequencyTable::new());
+        assert_eq!(table.bits(), &[0; 16]);
+        assert!(table.huffval().is_empty());
+        assert!(table.code(0).is_none());
+
+        let single = JpegHuffmanTable::from_frequencies(&FrequencyTable::from_data(b"xx"));
+        assert_eq!(single.huffval(), b"x");
+        assert_eq!(single.code(b'x').unwrap().len, 1);
+    }
+
+    #[test]
+    fn test_invalid_tables() {
+        let bits = [3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(matches!(JpegHuffmanTable::from_dht(&bits, b"abc"), Err(Error::InvalidCode(_))));
+        let bits = [0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(matches!(JpegHuffmanTable::from_dht(&bits, b"ab"), Err(Error::InvalidCode(_))));
+        assert!(matches!(JpegHuffmanTable::from_dht(&bits, b"aba"), Err(Error::InvalidCode(_))));
+        assert!(matches!(JpegHuffmanTable::read_dht_segment(&[0xff, 0xc4, 0x00, 0x13, 0x00]),
+            Err(Error::UnexpectedEof)));
+    }
+}
@@ -1,11 +1,41 @@
 extern crate bitstream;
 
+pub mod block_sort;
+mod canonical;
+mod code_book;
+mod context_coder;
+mod error;
+mod freq_table;
 mod freq_tree;
+mod header;
+mod fse;
+pub mod hpack;
+pub mod jpeg;
 mod huffman_encoder;
 mod huffman_decoder;
+pub mod lz77;
+mod multi_table;
+mod push_decoder;
+mod push_encoder;
+mod rle;
+pub mod pack_format;
+mod seekable_reader;
+mod tree_render;
+pub mod zstd_literals;
 
+pub use canonical::{CanonicalCode, code_lengths};
+pub use code_book::{Code, CodeBook, MAX_CODE_LEN};
+pub use context_coder::{ContextEncoder, ContextDecoder};
+pub use error::{Error, Result, TreeError};
+pub use freq_table::{FrequencyTable, histogram};
+pub use freq_tree::{FreqTreeNode, FreqNodeData, FreqTreeVal, FreqTreeComposit};
 pub use huffman_encoder::HuffmanEncoder;
-pub use huffman_decoder::HuffmanDecoder;
+pub use huffman_decoder::{HuffmanDecoder, DecodeLimits};
+pub use multi_table::{MultiTableEncoder, MultiTableDecoder};
+pub use push_decoder::{PushDecoder, DecodeStatus};
+pub use push_encoder::{PushEncoder, PushStreamDecoder};
+pub use rle::{RleEncoder, RleDecoder, RleStats};
+pub use seekable_reader::{SeekableHuffmanReader, SyncPoint};
 
 #[cfg(test)]
 mod tests {
@@ -24,7 +54,7 @@ mod tests {
 
         let v = h.encode();
 
-        let data = HuffmanDecoder::decode(v);
+        let data = HuffmanDecoder::decode(v).unwrap();
         let ds = String::from_utf8(data).unwrap();
 
         assert_eq!(s, ds);
@@ -41,10 +71,800 @@ mod tests {
         h.add_chunk(s.as_bytes());
         let v = h.encode();
 
-        let data = HuffmanDecoder::decode(v);
+        let data = HuffmanDecoder::decode(v).unwrap();
         let ds = String::from_utf8(data).unwrap();
 
 
         assert_eq!(s, ds);
     }
+
+    #[test]
+    fn huffman_single_and_empty_input() {
+        for s in &["", "a", "aaaaaaaa"] {
+            let mut h = HuffmanEncoder::new();
+            h.add_chunk(s.as_bytes());
+            let data = HuffmanDecoder::decode(h.encode()).unwrap();
+            assert_eq!(s.as_bytes(), &data[..]);
+        }
+    }
+
+    #[test]
+    fn huffman_explicit_frequencies() {
+        let mut sample = FrequencyTable::from_data(b"aaaabbbcc");
+        sample.merge(&FrequencyTable::from_data(b"ccccdd"));
+        assert_eq!(sample.get(b'c'), 6);
+        assert_eq!(sample.total(), 15);
+
+        let restored = FrequencyTable::decode(&sample.encode()).unwrap();
+        assert_eq!(restored, sample);
+
+        // 'z' is missing from the table but must still round trip
+        let s = "abcdzabcd";
+        let mut h = HuffmanEncoder::with_frequencies(restored);
+        h.add_chunk(s.as_bytes());
+        assert_eq!(h.frequencies().get(b'z'), 1);
+        let data = HuffmanDecoder::decode(h.encode()).unwrap();
+        assert_eq!(s.as_bytes(), &data[..]);
+    }
+
+    #[test]
+    fn huffman_deterministic_output() {
+        // every byte has the same count, so the tree is built entirely from ties
+        let s = "abcdefghijklmnopqrstuvwxyz".repeat(3);
+        let expected = {
+            let mut h = HuffmanEncoder::new();
+            h.add_chunk(s.as_bytes());
+            h.encode()
+        };
+
+        for _ in 0..10 {
+            let mut h = HuffmanEncoder::new();
+            h.add_chunk(s.as_bytes());
+            assert_eq!(h.encode(), expected);
+        }
+
+        // the chunking of the input or the origin of the frequencies must not
+        // change the output either
+        let mut h = HuffmanEncoder::with_frequencies(FrequencyTable::from_data(s.as_bytes()));
+        for piece in s.as_bytes().chunks(7) {
+            h.add_chunk(piece);
+        }
+        assert_eq!(h.encode(), expected);
+
+        let mut h = HuffmanEncoder::new();
+        h.add_chunk(b"aabbbc");
+        assert_eq!(h.encode(), vec![
+            0x04, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x58, 0x96, 0x3b, 0x08, 0xf1, 0x00,
+        ]);
+    }
+
+    #[test]
+    fn huffman_encoder_write_and_extend() {
+        use std::io::{Cursor, Write};
+
+        let s = "The quick brown fox jumps over the lazy dog. ".repeat(50);
+
+        let mut h = HuffmanEncoder::new();
+        std::io::copy(&mut Cursor::new(s.as_bytes()), &mut h).unwrap();
+        assert_eq!(HuffmanDecoder::decode(h.encode()).unwrap(), s.as_bytes());
+
+        let mut h = HuffmanEncoder::new();
+        let name = "ab";
+        write!(h, "{}-{}", 12, name).unwrap();
+        h.extend(b"cd");
+        h.extend(vec![b'e', b'f']);
+        h.extend(vec![&b"gh"[..], &b"ij"[..]]);
+        assert_eq!(HuffmanDecoder::decode(h.encode()).unwrap(), b"12-abcdefghij");
+
+        let h: HuffmanEncoder = s.bytes().filter(|b| *b != b' ').collect();
+        let expected: Vec<u8> = s.bytes().filter(|b| *b != b' ').collect();
+        assert_eq!(HuffmanDecoder::decode(h.encode()).unwrap(), expected);
+    }
+
+    #[test]
+    fn huffman_chunk_boundaries() {
+        let chunks: Vec<&[u8]> = vec![b"first message", b"", b"second", b"third and last message"];
+
+        let mut h = HuffmanEncoder::new();
+        h.set_record_chunks(true);
+        h.set_sync_interval(8);
+        h.extend(chunks.iter().cloned());
+        let v = h.encode();
+
+        assert_eq!(HuffmanDecoder::decode_slice(&v).unwrap(), chunks.concat());
+        assert_eq!(HuffmanDecoder::chunk_count(&v).unwrap(), 4);
+        assert_eq!(HuffmanDecoder::decode_chunks(&v).unwrap(), chunks);
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert_eq!(&HuffmanDecoder::decode_chunk(&v, i).unwrap()[..], *chunk);
+        }
+
+        match HuffmanDecoder::decode_chunk(&v, 4) {
+            Err(Error::NoSuchChunk { index: 4, count: 4 }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // without recorded chunks everything is one chunk
+        let mut h = HuffmanEncoder::new();
+        h.extend(chunks.iter().cloned());
+        let v = h.encode();
+        assert_eq!(HuffmanDecoder::decode_chunks(&v).unwrap(), vec![chunks.concat()]);
+
+        // recorded but empty
+        let mut h = HuffmanEncoder::new();
+        h.set_record_chunks(true);
+        assert!(HuffmanDecoder::decode_chunks(&h.encode()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn huffman_empty_chunk_index() {
+        let mut h = HuffmanEncoder::new();
+        h.set_record_chunks(true);
+        h.add_chunk(b"some data");
+        let v = h.encode();
+        assert_eq!(v[3], header::FLAG_CHUNK_INDEX);
+
+        // drop the only chunk start and claim there are none
+        let tree_len = (u32::from_le_bytes([v[0], v[1], v[2], 0])) as usize;
+        let index = 12 + tree_len;
+        let mut forged = v[..index].to_vec();
+        forged.extend_from_slice(&0u32.to_le_bytes());
+        forged.extend_from_slice(&v[index + 20..]);
+
+        match HuffmanDecoder::decode_chunks(&forged) {
+            Err(Error::InvalidHeader(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn huffman_encoder_borrowed_chunks() {
+        let s = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(4096)
+            .collect::<String>();
+        let (head, tail) = s.as_bytes().split_at(1000);
+
+        let mut copied = HuffmanEncoder::new();
+        copied.add_chunk(head);
+        copied.add_chunk(tail);
+
+        let mut borrowed = HuffmanEncoder::new();
+        borrowed.add_chunk_ref(head);
+        borrowed.add_chunk(b"");
+        borrowed.add_chunk_ref(tail);
+
+        let v = borrowed.encode();
+        assert_eq!(v, copied.encode());
+        assert_eq!(HuffmanDecoder::decode(v).unwrap(), s.as_bytes());
+    }
+
+    #[test]
+    fn huffman_push_decoder() {
+        let s = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(2000)
+            .collect::<String>();
+
+        let mut h = HuffmanEncoder::new();
+        h.set_sync_interval(100);
+        h.set_record_chunks(true);
+        h.add_chunk(s.as_bytes());
+        let v = h.encode();
+
+        // feeding one byte at a time splits the header and most codes
+        for piece_len in &[1, 3, 7, 64, v.len()] {
+            let mut decoder = PushDecoder::new();
+            let mut out = Vec::new();
+            let mut status = DecodeStatus::NeedMoreInput;
+
+            for piece in v.chunks(*piece_len) {
+                assert_eq!(status, DecodeStatus::NeedMoreInput);
+                status = decoder.feed(piece, &mut out).unwrap();
+            }
+
+            assert_eq!(status, DecodeStatus::Done);
+            assert!(decoder.is_done());
+            assert_eq!(out, s.as_bytes());
+            assert_eq!(decoder.feed(b"more", &mut out).unwrap(), DecodeStatus::Done);
+            assert_eq!(out.len(), s.len());
+        }
+
+        let mut decoder = PushDecoder::new();
+        let mut out = Vec::new();
+        assert_eq!(decoder.feed(&v[..v.len() - 1], &mut out).unwrap(), DecodeStatus::NeedMoreInput);
+    }
+
+    #[test]
+    fn huffman_push_decoder_empty_and_limits() {
+        let v = HuffmanEncoder::new().encode();
+        let mut out = Vec::new();
+        assert_eq!(PushDecoder::new().feed(&v, &mut out).unwrap(), DecodeStatus::Done);
+        assert!(out.is_empty());
+
+        let mut h = HuffmanEncoder::new();
+        h.add_chunk(&[7u8; 100]);
+        let v = h.encode();
+        let mut decoder = PushDecoder::with_limits(DecodeLimits { max_output: 99, ..DecodeLimits::default() });
+        match decoder.feed(&v, &mut out) {
+            Err(Error::LimitExceeded(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // a tree larger than 511 nodes can take fails on its size alone
+        let mut huge_tree = v[..4].to_vec();
+        huge_tree[..3].copy_from_slice(&[0xff, 0xff, 0xff]);
+        match PushDecoder::new().feed(&huge_tree, &mut out) {
+            Err(Error::LimitExceeded("tree nodes")) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // so does an index larger than the header limit, before it is read
+        let mut h = HuffmanEncoder::new();
+        h.set_sync_interval(10);
+        h.add_chunk(&[7u8; 100]);
+        let v = h.encode();
+        let tree_size = u32::from_le_bytes([v[0], v[1], v[2], 0]) as usize;
+        let mut huge_index = v[..16 + tree_size].to_vec();
+        huge_index[12 + tree_size..].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut decoder = PushDecoder::new();
+        assert_eq!(decoder.feed(&huge_index[..14 + tree_size], &mut out).unwrap(), DecodeStatus::NeedMoreInput);
+        match decoder.feed(&huge_index[14 + tree_size..], &mut out) {
+            Err(Error::LimitExceeded("header size")) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let limits = DecodeLimits { max_header_len: 12 + tree_size, ..DecodeLimits::default() };
+        match PushDecoder::with_limits(limits).feed(&v, &mut out) {
+            Err(Error::LimitExceeded("header size")) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn push_encoder_flush_points() {
+        let messages = ["GET /index.html", "", "HTTP/1.1 200 OK", "\x00\x7f\u{1}binary"];
+
+        let mut trained = FrequencyTable::new();
+        trained.add_data(b"GET POST HTTP/1.1 200 OK /index.html");
+
+        for table in &[None, Some(&trained)] {
+            let mut encoder = match table {
+                Some(table) => PushEncoder::with_frequencies(table),
+                None => PushEncoder::new(),
+            };
+            let mut decoder = PushStreamDecoder::new();
+            let mut stream = Vec::new();
+            let mut out = Vec::new();
+
+            // after every flush the receiver decodes everything sent so far
+            let mut sent = Vec::new();
+            for message in &messages {
+                let start = stream.len();
+                for piece in message.as_bytes().chunks(4) {
+                    encoder.push(piece, &mut stream);
+                }
+                encoder.flush(&mut stream);
+                sent.extend_from_slice(message.as_bytes());
+
+                assert_eq!(decoder.feed(&stream[start..], &mut out).unwrap(), DecodeStatus::NeedMoreInput);
+                assert_eq!(out, sent);
+            }
+
+            let start = stream.len();
+            encoder.finish(&mut stream);
+            assert_eq!(decoder.feed(&stream[start..], &mut out).unwrap(), DecodeStatus::Done);
+            assert_eq!(out, sent);
+
+            // the stream can be split anywhere
+            let mut decoder = PushStreamDecoder::new();
+            let mut out = Vec::new();
+            for piece in stream.chunks(1) {
+                decoder.feed(piece, &mut out).unwrap();
+            }
+            assert!(decoder.is_done());
+            assert_eq!(out, sent);
+        }
+    }
+
+    #[test]
+    fn push_encoder_trained_table() {
+        let s = "the quick brown fox jumps over the lazy dog. ".repeat(20);
+
+        let mut fixed = Vec::new();
+        let mut encoder = PushEncoder::new();
+        encoder.push(s.as_bytes(), &mut fixed);
+        encoder.finish(&mut fixed);
+        assert!(encoder_code_lengths(&PushEncoder::new()).all(|l| l == 8 || l == 9));
+
+        let mut trained = Vec::new();
+        let mut encoder = PushEncoder::with_frequencies(&FrequencyTable::from_data(s.as_bytes()));
+        assert!(encoder.code().code(0).is_some());
+        encoder.push(s.as_bytes(), &mut trained);
+        encoder.finish(&mut trained);
+        assert!(trained.len() < fixed.len() * 2 / 3);
+
+        let mut out = Vec::new();
+        assert_eq!(PushStreamDecoder::new().feed(&trained, &mut out).unwrap(), DecodeStatus::Done);
+        assert_eq!(out, s.as_bytes());
+    }
+
+    fn encoder_code_lengths(encoder: &PushEncoder) -> impl Iterator<Item = u8> + '_ {
+        encoder.code().lengths().iter().cloned()
+    }
+
+    #[test]
+    fn context_encoder_roundtrip() {
+        let log = (0..200).map(|i| format!("2024-01-{:02} INFO request id={} status=200\n", i % 28 + 1, i * 37))
+            .collect::<String>();
+
+        let mut order0 = HuffmanEncoder::new();
+        order0.add_chunk(log.as_bytes());
+        let order0 = order0.encode();
+
+        let mut encoder = ContextEncoder::new();
+        for line in log.as_bytes().chunks(50) {
+            encoder.add_chunk(line);
+        }
+        assert_eq!(encoder.context_frequencies(b'\n').get(b'2'), 199);
+        let encoded = encoder.encode();
+        assert!(encoded.len() < order0.len() * 3 / 4);
+        assert_eq!(ContextDecoder::decode(&encoded).unwrap(), log.as_bytes());
+
+        // limiting the tables merges contexts but still decodes
+        let (_, tables) = encoder.table_map();
+        assert!(tables > 4);
+        encoder.set_max_tables(4);
+        let (map, tables) = encoder.table_map();
+        assert_eq!(tables, 4);
+        assert!(map.iter().all(|t| *t < 4));
+        assert_eq!(ContextDecoder::decode(&encoder.encode()).unwrap(), log.as_bytes());
+    }
+
+    #[test]
+    fn context_encoder_small_inputs() {
+        use std::io::Write;
+
+        for data in &[&b""[..], b"a", b"aaaa", b"ab"] {
+            let mut encoder = ContextEncoder::new();
+            encoder.write_all(data).unwrap();
+            assert_eq!(ContextDecoder::decode(&encoder.encode()).unwrap(), *data);
+        }
+
+        let mut encoder = ContextEncoder::new();
+        encoder.add_chunk(b"abcabc");
+        let encoded = encoder.encode();
+        assert!(matches!(ContextDecoder::decode(&encoded[..encoded.len() - 1]), Err(Error::UnexpectedEof)));
+
+        let mut bad_map = encoded.clone();
+        bad_map[9 + b'c' as usize] = 200;
+        assert!(matches!(ContextDecoder::decode(&bad_map), Err(Error::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn rle_encoder_sensor_dump() {
+        // readings separated by long stretches of zero fill
+        let mut dump = Vec::new();
+        for i in 0..50u32 {
+            dump.extend_from_slice(&(i * 7919).to_le_bytes());
+            dump.extend(std::iter::repeat_n(0, 4000 + i as usize));
+        }
+
+        let mut encoder = RleEncoder::new();
+        for piece in dump.chunks(1000) {
+            encoder.add_chunk(piece);
+        }
+        let encoded = encoder.encode();
+        assert_eq!(RleDecoder::decode(&encoded).unwrap(), dump);
+
+        let stats = encoder.stats();
+        assert_eq!(stats.encoded_len, encoded.len());
+        assert_eq!(stats.runs, 50);
+        assert!(stats.run_bytes >= 50 * 4000);
+        assert!(stats.symbols < 400);
+        assert!(stats.saved() > stats.huffman_len as i64 * 9 / 10);
+
+        let limits = DecodeLimits { max_output: 1000, ..DecodeLimits::unlimited() };
+        assert!(matches!(RleDecoder::decode_with_limits(&encoded, &limits), Err(Error::LimitExceeded(_))));
+    }
+
+    #[test]
+    fn rle_encoder_short_runs() {
+        let data = b"aabbbcccc dddddddd e";
+        let mut encoder = RleEncoder::new();
+        encoder.add_chunk(data);
+        let stats = encoder.stats();
+        assert_eq!((stats.runs, stats.run_bytes), (1, 8));
+        assert_eq!(stats.symbols, data.len() as u64 - 8 + 3);
+        assert_eq!(RleDecoder::decode(&encoder.encode()).unwrap(), &data[..]);
+
+        encoder.set_min_run(3);
+        assert_eq!(encoder.stats().runs, 3);
+        assert_eq!(RleDecoder::decode(&encoder.encode()).unwrap(), &data[..]);
+
+        for data in &[&b""[..], b"x", &[0; 100]] {
+            let mut encoder = RleEncoder::new();
+            encoder.add_chunk(data);
+            assert_eq!(RleDecoder::decode(&encoder.encode()).unwrap(), *data);
+        }
+
+        let encoded = encoder.encode();
+        assert!(matches!(RleDecoder::decode(&encoded[..encoded.len() - 1]), Err(Error::UnexpectedEof)));
+    }
+
+    #[test]
+    fn rle_decoder_long_run() {
+        let mut data = b"abc".to_vec();
+        data.extend(std::iter::repeat_n(0, 1_000_000));
+
+        let mut encoder = RleEncoder::new();
+        encoder.add_chunk(&data);
+        let encoded = encoder.encode();
+        assert!(encoded.len() < 200);
+        assert_eq!(RleDecoder::decode(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn rle_decoder_forged_run() {
+        use std::io::Write;
+        use bitstream::BitWriter;
+
+        // one byte followed by a single run of 2^39 bytes, the longest run
+        // length symbol with all extra bits 0
+        let (escape, longest) = (256, 256 + 1 + 39);
+        let mut lengths = vec![0u8; 256 + 1 + 40];
+        lengths[b'a' as usize] = 1;
+        lengths[escape] = 2;
+        lengths[longest] = 2;
+        let code = CanonicalCode::from_lengths(&lengths).unwrap();
+
+        let mut forged = Vec::new();
+        forged.extend_from_slice(&((1u64 << 39) + 1).to_le_bytes());
+        crate::canonical::write_packed_lengths(&mut forged, &lengths);
+        let mut bit_writer = BitWriter::with_writer(&mut forged);
+        code.write_symbol(&mut bit_writer, b'a' as usize).unwrap();
+        code.write_symbol(&mut bit_writer, escape).unwrap();
+        code.write_symbol(&mut bit_writer, longest).unwrap();
+        bit_writer.add_bits(0, 39).unwrap();
+        bit_writer.flush().unwrap();
+        drop(bit_writer);
+
+        match RleDecoder::decode(&forged) {
+            Err(Error::LimitExceeded(_)) => (),
+            other => panic!("unexpected result {:?}", other.map(|v| v.len())),
+        }
+        let limits = DecodeLimits { max_output: 1 << 30, ..DecodeLimits::unlimited() };
+        assert!(matches!(RleDecoder::decode_with_limits(&forged, &limits), Err(Error::LimitExceeded(_))));
+
+        // a run past the claimed size is invalid
+        let mut short = forged.clone();
+        short[..8].copy_from_slice(&5u64.to_le_bytes());
+        assert!(matches!(RleDecoder::decode(&short), Err(Error::InvalidCode(_))));
+    }
+
+    #[test]
+    fn multi_table_encoder_roundtrip() {
+        // parts with different statistics pick different tables
+        let mut data = Vec::new();
+        let mut rng = rand::thread_rng();
+        for part in 0..8 {
+            let alphabet: &[u8] = if part % 2 == 0 { b"ACGT" } else { b"0123456789abcdef" };
+            data.extend((0..1000).map(|_| alphabet[rng.gen_range(0, alphabet.len())]));
+        }
+
+        let mut single = HuffmanEncoder::new();
+        single.add_chunk(&data);
+        let single = single.encode();
+
+        let mut encoder = MultiTableEncoder::new();
+        encoder.add_chunk(&data);
+        let (selectors, tables) = encoder.table_selectors();
+        assert_eq!(tables, 6);
+        assert_eq!(selectors.len(), 160);
+        assert_ne!(selectors[0], selectors[20]);
+
+        let encoded = encoder.encode();
+        assert!(encoded.len() < single.len() * 9 / 10);
+        assert_eq!(MultiTableDecoder::decode(&encoded).unwrap(), data);
+
+        encoder.set_max_tables(1);
+        assert_eq!(encoder.table_selectors(), (vec![0; 160], 1));
+        assert_eq!(MultiTableDecoder::decode(&encoder.encode()).unwrap(), data);
+    }
+
+    #[test]
+    fn multi_table_encoder_small_inputs() {
+        for data in &[&b""[..], b"a", b"abababab", &[7; 51]] {
+            let mut encoder = MultiTableEncoder::new();
+            encoder.add_chunk(data);
+            assert_eq!(MultiTableDecoder::decode(&encoder.encode()).unwrap(), *data);
+        }
+
+        let mut encoder = MultiTableEncoder::new();
+        encoder.add_chunk(b"hello multi table world");
+        let encoded = encoder.encode();
+        assert!(matches!(MultiTableDecoder::decode(&encoded[..encoded.len() - 1]), Err(Error::UnexpectedEof)));
+
+        let mut bad = encoded.clone();
+        bad[8] = 7;
+        assert!(matches!(MultiTableDecoder::decode(&bad), Err(Error::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn huffman_encode_into_buffers() {
+        let s = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(3000)
+            .collect::<String>();
+
+        let mut h = HuffmanEncoder::new();
+        h.set_sync_interval(500);
+        h.add_chunk(s.as_bytes());
+
+        let len = h.encoded_len();
+        assert!(len <= h.max_encoded_len(s.len()));
+
+        let mut prefixed = b"prefix".to_vec();
+        h.encode_into(&mut prefixed);
+        assert_eq!(prefixed.len(), 6 + len);
+        assert_eq!(&prefixed[..6], b"prefix");
+
+        let mut buf = vec![0u8; h.max_encoded_len(s.len())];
+        let n = h.encode_to_slice(&mut buf).unwrap();
+        assert_eq!(n, len);
+        assert_eq!(&buf[..n], &prefixed[6..]);
+        assert_eq!(HuffmanDecoder::decode_slice(&buf[..n]).unwrap(), s.as_bytes());
+
+        match h.encode_to_slice(&mut buf[..len - 1]) {
+            Err(Error::BufferTooSmall { needed, .. }) => assert_eq!(needed, len as u64),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        assert_eq!(h.encode(), &prefixed[6..]);
+    }
+
+    #[test]
+    fn huffman_max_encoded_len() {
+        // every byte value once gives every byte an 8 bit code
+        let all: Vec<u8> = (0..=255u8).collect();
+        let mut h = HuffmanEncoder::new();
+        h.add_chunk(&all);
+        assert!(h.encoded_len() <= HuffmanEncoder::new().max_encoded_len(all.len()));
+
+        let skewed = FrequencyTable::from_data(&[0u8; 100]);
+        let mut h = HuffmanEncoder::with_frequencies(skewed.clone());
+        h.add_chunk(&all);
+        assert!(h.encoded_len() <= HuffmanEncoder::with_frequencies(skewed.clone()).max_encoded_len(all.len()));
+
+        assert_eq!(HuffmanEncoder::with_frequencies(skewed).max_encoded_len(usize::MAX), usize::MAX);
+
+        // the chunk index of many small chunks
+        let mut h = HuffmanEncoder::new();
+        h.set_record_chunks(true);
+        h.extend(all.chunks(1));
+        let mut out = vec![0u8; h.max_encoded_len(all.len())];
+        assert_eq!(h.encode_to_slice(&mut out).unwrap(), h.encoded_len());
+
+        let mut h = HuffmanEncoder::new();
+        h.set_record_chunks(true);
+        let bound = h.max_encoded_len(all.len());
+        h.add_chunk(&all);
+        assert!(h.encoded_len() <= bound);
+    }
+
+    #[test]
+    fn huffman_decode_borrowed() {
+        let s = "borrowed bytes are decoded in place";
+        let mut h = HuffmanEncoder::new();
+        h.add_chunk(s.as_bytes());
+        let v = h.encode();
+
+        assert_eq!(HuffmanDecoder::decode_slice(&v).unwrap(), s.as_bytes());
+
+        let mut out = [0u8; 64];
+        let n = HuffmanDecoder::decode_into(&v, &mut out).unwrap();
+        assert_eq!(&out[..n], s.as_bytes());
+
+        let mut small = [0u8; 8];
+        match HuffmanDecoder::decode_into(&v, &mut small) {
+            Err(Error::BufferTooSmall { needed, available: 8 }) => assert_eq!(needed, s.len() as u64),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        match HuffmanDecoder::decode_into(&v[..v.len() - 2], &mut out) {
+            Err(Error::UnexpectedEof) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn huffman_decode_limits() {
+        let mut h = HuffmanEncoder::new();
+        h.add_chunk(&[0u8; 1000]);
+        let v = h.encode();
+
+        assert_eq!(HuffmanDecoder::decode(v.clone()).unwrap().len(), 1000);
+
+        let limits = DecodeLimits { max_output: 999, ..DecodeLimits::default() };
+        match HuffmanDecoder::decode_with_limits(&v, &limits) {
+            Err(Error::LimitExceeded(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let limits = DecodeLimits { max_tree_nodes: 2, ..DecodeLimits::default() };
+        match HuffmanDecoder::decode_with_limits(&v, &limits) {
+            Err(Error::LimitExceeded(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // claim a terabyte of output from a handful of bytes
+        let mut bomb = v[..v.len() - 125].to_vec();
+        bomb[4..12].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        match HuffmanDecoder::decode(bomb.clone()) {
+            Err(Error::LimitExceeded(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match HuffmanDecoder::decode_with_limits(&bomb, &DecodeLimits::unlimited()) {
+            Err(Error::UnexpectedEof) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // a ratio above the limit fails before the output buffer is checked
+        let claimed = 8 * bomb.len() as u64 + 1;
+        bomb[4..12].copy_from_slice(&claimed.to_le_bytes());
+        let mut out = vec![0u8; 8 * bomb.len() + 1];
+        match HuffmanDecoder::decode_into(&bomb, &mut out) {
+            Err(Error::LimitExceeded(_)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn huffman_code_book() {
+        let mut h = HuffmanEncoder::new();
+        h.add_chunk(b"aaaabbc");
+        let book = h.code_book();
+
+        assert_eq!(book.code(b'a'), Some(Code { bits: 0b1, len: 1 }));
+        assert_eq!(book.code(b'b'), Some(Code { bits: 0b01, len: 2 }));
+        assert_eq!(book.code(b'c'), Some(Code { bits: 0b00, len: 2 }));
+        assert_eq!(book.code(b'd'), None);
+        assert_eq!(book.code(b'b').unwrap().to_string(), "01");
+
+        let order: Vec<u8> = book.iter().map(|(b, _)| b).collect();
+        assert_eq!(order, b"abc".to_vec());
+        assert_eq!(book.symbol_count(), 3);
+        assert_eq!(book.min_depth(), 1);
+        assert_eq!(book.max_depth(), 2);
+        assert_eq!(book.kraft_sum(), 1.0);
+
+        let decoded_book = HuffmanDecoder::read_code_book(&h.encode()).unwrap();
+        assert_eq!(decoded_book, book);
+    }
+
+    #[test]
+    fn histogram_counts() {
+        let data: Vec<u8> = (0..1027u32).map(|i| (i % 7) as u8).collect();
+        let counts = histogram(&data);
+
+        for byte in 0..7u8 {
+            let expected = data.iter().filter(|b| **b == byte).count() as u64;
+            assert_eq!(counts[byte as usize], expected);
+        }
+        assert_eq!(counts.iter().sum::<u64>(), 1027);
+        assert_eq!(histogram(&[]), [0u64; 256]);
+    }
+
+    #[test]
+    fn frequency_table_decode_errors() {
+        assert!(FrequencyTable::decode(&[]).is_err());
+        assert!(FrequencyTable::decode(&[1, 0, b'a']).is_err());
+        assert!(FrequencyTable::decode(&[1, 0, b'a', 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+
+        let mut twice = vec![2, 0];
+        twice.extend_from_slice(&[b'b', 1, 0, 0, 0, 0, 0, 0, 0]);
+        twice.extend_from_slice(&[b'a', 1, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(FrequencyTable::decode(&twice).is_err());
+    }
+
+    #[test]
+    fn huffman_sync_index_roundtrip() {
+        let s = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(4096)
+            .collect::<String>();
+
+        let mut h = HuffmanEncoder::new();
+        h.set_sync_interval(256);
+        h.add_chunk(s.as_bytes());
+        let v = h.encode();
+
+        let data = HuffmanDecoder::decode(v).unwrap();
+        assert_eq!(s.as_bytes(), &data[..]);
+    }
+
+    #[test]
+    fn huffman_seekable_reader() {
+        use std::io::{Read, Seek, SeekFrom};
+        use bitstream::VecStream;
+
+        let s = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(10000)
+            .collect::<String>();
+
+        let mut h = HuffmanEncoder::new();
+        h.set_sync_interval(1000);
+        h.add_chunk(s.as_bytes());
+        let v = h.encode();
+
+        let mut reader = SeekableHuffmanReader::new(VecStream::from_vec(v)).unwrap();
+        assert_eq!(reader.original_size(), 10000);
+        assert_eq!(reader.sync_points().len(), 9);
+
+        let mut buf = [0u8; 100];
+        for &offset in &[5000u64, 0, 1999, 2000, 9950, 4321] {
+            reader.seek(SeekFrom::Start(offset)).unwrap();
+            let n = reader.read(&mut buf).unwrap();
+            let start = offset as usize;
+            let end = std::cmp::min(start + buf.len(), s.len());
+            assert_eq!(n, end - start);
+            assert_eq!(&buf[..n], &s.as_bytes()[start..end]);
+        }
+
+        reader.seek(SeekFrom::End(-10)).unwrap();
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(&tail[..], &s.as_bytes()[9990..]);
+
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        let mut all = String::new();
+        reader.read_to_string(&mut all).unwrap();
+        assert_eq!(all, s);
+    }
+
+    #[test]
+    fn huffman_seekable_reader_without_index() {
+        use std::io::{Cursor, Read, Seek, SeekFrom};
+
+        let s = "the quick brown fox jumps over the lazy dog".to_owned();
+        let mut h = HuffmanEncoder::new();
+        h.add_chunk(s.as_bytes());
+        let v = h.encode();
+
+        let mut reader = SeekableHuffmanReader::new(Cursor::new(v)).unwrap();
+        assert!(reader.sync_points().is_empty());
+
+        reader.seek(SeekFrom::Start(16)).unwrap();
+        let mut word = [0u8; 3];
+        reader.read_exact(&mut word).unwrap();
+        assert_eq!(&word, b"fox");
+
+        reader.seek(SeekFrom::Current(-7)).unwrap();
+        reader.read_exact(&mut word).unwrap();
+        assert_eq!(&word, b"own");
+    }
+
+    #[test]
+    fn huffman_seekable_reader_bad_sync_point() {
+        use std::io::Cursor;
+
+        let s = "the quick brown fox jumps over the lazy dog".repeat(10);
+        let mut h = HuffmanEncoder::new();
+        h.set_sync_interval(100);
+        h.add_chunk(s.as_bytes());
+        let v = h.encode();
+
+        // the bit offset of the last sync point is the last 8 bytes of the
+        // index, which ends where the coded data starts
+        let tree_size = u32::from_le_bytes([v[0], v[1], v[2], 0]) as usize;
+        let count = u32::from_le_bytes([v[12 + tree_size], v[13 + tree_size], v[14 + tree_size], v[15 + tree_size]]) as usize;
+        let last = 12 + tree_size + 4 + 16 * count - 8;
+
+        for bit_offset in [u64::MAX, 430 * 64 + 1, (v.len() as u64 + 1) * 8] {
+            let mut bad = v.clone();
+            bad[last..last + 8].copy_from_slice(&bit_offset.to_le_bytes());
+            assert!(matches!(SeekableHuffmanReader::new(Cursor::new(bad)), Err(Error::InvalidHeader(_))));
+        }
+    }
 }
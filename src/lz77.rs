@@ -0,0 +1,445 @@
+//! An LZ77 front end that turns the crate into a general purpose compressor,
+//! along the lines of DEFLATE.
+//!
+//! A MatchFinder replaces repeated strings with a length and the distance
+//! back to an earlier copy, found through hash chains. The literals and match
+//! lengths share one alphabet and the distances have their own, and each
+//! alphabet gets a huffman code built from the symbol counts. Lengths and
+//! distances are coded as in DEFLATE, as a symbol for a range of values
+//! followed by extra bits for the position in the range. The distance codes
+//! continue past the 30 of DEFLATE for windows larger than 32 KiB.
+//!
+//! The compressed format holds:
+//!
+//! * `u64` little endian: size of the original data
+//! * `u8`: the number of distance symbols
+//! * the code length of every literal/length symbol and then of every
+//!   distance symbol, four bits each with the high bits first, padded with
+//!   0 bits to a byte boundary
+//! * the coded data, padded with 0 bits to a byte boundary
+
+use std::cmp::{max, min};
+use std::io::Write;
+
+use crate::canonical::{CanonicalCode, MAX_PACKED_CODE_LEN, code_lengths, read_packed_lengths, write_packed_lengths};
+use crate::error::{Error, Result};
+use crate::header::read_orig_size;
+use crate::huffman_decoder::DecodeLimits;
+
+use bitstream::{BitWriter, ReadBits, SliceBitReader};
+
+/// The shortest match that is coded as a match.
+pub const MIN_MATCH: usize = 3;
+
+/// The longest match.
+pub const MAX_MATCH: usize = 258;
+
+/// The largest window a MatchFinder can use.
+pub const MAX_WINDOW_SIZE: usize = 1 << 22;
+
+/// The number of literal/length symbols: the byte values followed by the
+/// length symbols.
+const LITLEN_SYMBOLS: usize = 256 + LENGTH_BASE.len();
+
+/// The largest ratio of original to compressed size: a match of MAX_MATCH
+/// bytes takes at least a one bit length and a one bit distance code.
+const MAX_RATIO: u64 = MAX_MATCH as u64 * 8 / 2;
+
+const HASH_BITS: u32 = 15;
+
+/// No position, in the hash chains.
+const NONE: usize = usize::MAX;
+
+/// The smallest length of every length symbol, and the number of extra bits
+/// that follow it, as in DEFLATE.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// A literal byte or a match as found by a MatchFinder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    /// A byte that is not part of a match.
+    Literal(u8),
+    /// A copy of length bytes starting distance bytes back.
+    Match { length: u16, distance: u32 },
+}
+
+/// Finds repeated strings with hash chains: every position is linked to the
+/// previous position whose next three bytes hash to the same value, and the
+/// chain is followed to find the longest match within the window.
+///
+/// With lazy matching a match is not taken right away if a following
+/// position starts a longer one; a literal is emitted instead and the longer
+/// match is taken. The lazy level is the number of following positions
+/// checked, 0 taking every match greedily.
+#[derive(Clone, Debug)]
+pub struct MatchFinder {
+    window_size: usize,
+    max_chain: usize,
+    lazy_level: usize,
+}
+
+impl Default for MatchFinder {
+    fn default() -> MatchFinder {
+        MatchFinder::new()
+    }
+}
+
+impl MatchFinder {
+    /// Create a new MatchFinder with the window of DEFLATE, 32 KiB, a chain
+    /// limit of 128 and a lazy level of 1.
+    pub fn new() -> MatchFinder {
+        MatchFinder {
+            window_size: 1 << 15,
+            max_chain: 128,
+            lazy_level: 1,
+        }
+    }
+
+    /// Set how far back matches may start.
+    ///
+    /// Panics if window_size is not a power of two between 256 and
+    /// MAX_WINDOW_SIZE.
+    pub fn set_window_size(&mut self, window_size: usize) {
+        assert!(window_size.is_power_of_two() && (256..=MAX_WINDOW_SIZE).contains(&window_size),
+            "invalid window size {}", window_size);
+        self.window_size = window_size;
+    }
+
+    /// Set the number of earlier positions looked at for every match. Longer
+    /// chains find better matches but take more time.
+    ///
+    /// Panics if max_chain is 0.
+    pub fn set_max_chain(&mut self, max_chain: usize) {
+        assert!(max_chain > 0, "the chain limit must be at least 1");
+        self.max_chain = max_chain;
+    }
+
+    /// Set the number of following positions checked for a longer match
+    /// before a match is taken.
+    pub fn set_lazy_level(&mut self, lazy_level: usize) {
+        self.lazy_level = lazy_level;
+    }
+
+    /// Splits data into literals and matches.
+    pub fn find_tokens(&self, data: &[u8]) -> Vec<Token> {
+        let mut chains = HashChains::new(data, self.window_size);
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let (length, distance) = self.longest_match(&mut chains, pos);
+            let deferred = length >= MIN_MATCH && (1..=self.lazy_level)
+                .any(|step| self.longest_match(&mut chains, pos + step).0 > length);
+
+            if length < MIN_MATCH || deferred {
+                tokens.push(Token::Literal(data[pos]));
+                pos += 1;
+            } else {
+                tokens.push(Token::Match { length: length as u16, distance: distance as u32 });
+                pos += length;
+            }
+        }
+        tokens
+    }
+
+    /// Compresses data.
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        encode_tokens(&self.find_tokens(data), data.len(), distance_symbols(self.window_size))
+    }
+
+    /// Returns the length and distance of the longest match at pos.
+    fn longest_match(&self, chains: &mut HashChains, pos: usize) -> (usize, usize) {
+        let data = chains.data;
+        if pos + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+        chains.insert_until(pos);
+
+        let limit = min(MAX_MATCH, data.len() - pos);
+        let mut best = (0, 0);
+        let mut candidate = chains.head[chains.hash(pos)];
+        // positions after pos are in the chains when a lazy match looked
+        // ahead of it
+        while candidate != NONE && candidate >= pos {
+            candidate = chains.prev[candidate & (self.window_size - 1)];
+        }
+        for _ in 0..self.max_chain {
+            if candidate == NONE || candidate >= pos || pos - candidate > self.window_size {
+                break;
+            }
+
+            let length = data[candidate..].iter().zip(&data[pos..pos + limit])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best.0 {
+                best = (length, pos - candidate);
+                if length == limit {
+                    break;
+                }
+            }
+            candidate = chains.prev[candidate & (self.window_size - 1)];
+        }
+        best
+    }
+}
+
+/// The heads and links of the hash chains of a MatchFinder.
+struct HashChains<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    /// the previous position with the same hash, by position modulo the
+    /// window size
+    prev: Vec<usize>,
+    /// the positions before this one have been inserted
+    inserted: usize,
+}
+
+impl<'a> HashChains<'a> {
+    fn new(data: &'a [u8], window_size: usize) -> HashChains<'a> {
+        HashChains {
+            data,
+            head: vec![NONE; 1 << HASH_BITS],
+            prev: vec![NONE; window_size],
+            inserted: 0,
+        }
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let bytes = &self.data[pos..pos + MIN_MATCH];
+        let val = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        (val.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert_until(&mut self, pos: usize) {
+        let mask = self.prev.len() - 1;
+        while self.inserted < pos {
+            let h = self.hash(self.inserted);
+            self.prev[self.inserted & mask] = self.head[h];
+            self.head[h] = self.inserted;
+            self.inserted += 1;
+        }
+    }
+}
+
+/// Compresses data with the default MatchFinder.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    MatchFinder::new().compress(data)
+}
+
+/// Decompresses data produced by a MatchFinder, with a ratio limit that
+/// accepts everything a MatchFinder produces and no limit on the output
+/// size.
+pub fn decompress(buf: &[u8]) -> Result<Vec<u8>> {
+    let limits = DecodeLimits { max_ratio: MAX_RATIO, ..DecodeLimits::unlimited() };
+    decompress_with_limits(buf, &limits)
+}
+
+/// Decompresses data produced by a MatchFinder, failing with
+/// Error::LimitExceeded if the data would exceed the output or ratio limit.
+pub fn decompress_with_limits(buf: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>> {
+    let header = buf.get(..9).ok_or(Error::UnexpectedEof)?;
+    let orig_size = read_orig_size(buf, limits)?;
+
+    let dist_symbols = header[8] as usize;
+    if dist_symbols < 2 || dist_symbols > distance_symbols(MAX_WINDOW_SIZE) {
+        return Err(Error::InvalidHeader("invalid number of distance symbols"));
+    }
+    let symbols = LITLEN_SYMBOLS + dist_symbols;
+    let (lengths, input) = read_packed_lengths(&buf[header.len()..], symbols)?;
+    let litlen = CanonicalCode::from_lengths(&lengths[..LITLEN_SYMBOLS])?;
+    let dist = CanonicalCode::from_lengths(&lengths[LITLEN_SYMBOLS..])?;
+
+    let mut reader = SliceBitReader::new(input);
+    let mut out = Vec::with_capacity(min(orig_size, input.len() as u64 * MAX_RATIO) as usize);
+    while (out.len() as u64) < orig_size {
+        let symbol = litlen.read_symbol(&mut reader)?;
+        if symbol < 256 {
+            out.push(symbol as u8);
+            continue;
+        }
+
+        let l = symbol - 256;
+        let length = LENGTH_BASE[l] as usize + read_extra(&mut reader, LENGTH_EXTRA[l])? as usize;
+        let (base, extra) = distance_range(dist.read_symbol(&mut reader)?);
+        let distance = base + read_extra(&mut reader, extra)? as usize;
+        if distance > out.len() {
+            return Err(Error::InvalidCode("match starts before the data"));
+        }
+        if (out.len() + length) as u64 > orig_size {
+            return Err(Error::InvalidCode("match runs past the end of the data"));
+        }
+
+        // the copy may overlap the bytes it produces
+        let start = out.len() - distance;
+        for i in start..start + length {
+            out.push(out[i]);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Huffman codes tokens that decode to orig_len bytes, with a distance
+/// alphabet of dist_symbols symbols.
+fn encode_tokens(tokens: &[Token], orig_len: usize, dist_symbols: usize) -> Vec<u8> {
+    let mut litlen_freqs = vec![0u64; LITLEN_SYMBOLS];
+    let mut dist_freqs = vec![0u64; dist_symbols];
+    for token in tokens {
+        match *token {
+            Token::Literal(b) => litlen_freqs[b as usize] += 1,
+            Token::Match { length, distance } => {
+                litlen_freqs[256 + length_symbol(length as usize)] += 1;
+                dist_freqs[distance_symbol(distance as usize)] += 1;
+            },
+        }
+    }
+
+    let mut lengths = code_lengths(&litlen_freqs, MAX_PACKED_CODE_LEN);
+    lengths.extend(code_lengths(&dist_freqs, MAX_PACKED_CODE_LEN));
+    let litlen = CanonicalCode::from_lengths(&lengths[..LITLEN_SYMBOLS]).expect("computed lengths form a code");
+    let dist = CanonicalCode::from_lengths(&lengths[LITLEN_SYMBOLS..]).expect("computed lengths form a code");
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&(orig_len as u64).to_le_bytes());
+    out.push(dist_symbols as u8);
+    write_packed_lengths(&mut out, &lengths);
+
+    let mut bit_writer = BitWriter::with_writer(&mut out);
+    for token in tokens {
+        match *token {
+            Token::Literal(b) => litlen.write_symbol(&mut bit_writer, b as usize).unwrap(),
+            Token::Match { length, distance } => {
+                let l = length_symbol(length as usize);
+                litlen.write_symbol(&mut bit_writer, 256 + l).unwrap();
+                bit_writer.add_bits(length as u64 - LENGTH_BASE[l] as u64, LENGTH_EXTRA[l]).unwrap();
+
+                let d = distance_symbol(distance as usize);
+                let (base, extra) = distance_range(d);
+                dist.write_symbol(&mut bit_writer, d).unwrap();
+                bit_writer.add_bits((distance as usize - base) as u64, extra).unwrap();
+            },
+        }
+    }
+    bit_writer.flush().unwrap();
+    drop(bit_writer);
+    out
+}
+
+fn length_symbol(length: usize) -> usize {
+    LENGTH_BASE.iter().rposition(|base| *base as usize <= length).unwrap()
+}
+
+/// The number of distance symbols needed for a window.
+fn distance_symbols(window_size: usize) -> usize {
+    max(2, 2 * window_size.trailing_zeros() as usize)
+}
+
+fn distance_symbol(distance: usize) -> usize {
+    let v = distance - 1;
+    if v < 4 {
+        return v;
+    }
+    let high = (usize::BITS - 1 - v.leading_zeros()) as usize;
+    2 * high + ((v >> (high - 1)) & 1)
+}
+
+/// Returns the smallest distance of a distance symbol and the number of
+/// extra bits that follow it.
+fn distance_range(symbol: usize) -> (usize, u8) {
+    if symbol < 4 {
+        return (symbol + 1, 0);
+    }
+    let extra = symbol / 2 - 1;
+    (((2 | (symbol & 1)) << extra) + 1, extra as u8)
+}
+
+fn read_extra<R: ReadBits + ?Sized>(reader: &mut R, count: u8) -> Result<u64> {
+    let mut val = 0;
+    for _ in 0..count {
+        val = (val << 1) | reader.get_bit().ok_or(Error::UnexpectedEof)? as u64;
+    }
+    Ok(val)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbols() {
+        assert_eq!(length_symbol(3), 0);
+        assert_eq!(length_symbol(12), 8);
+        assert_eq!(length_symbol(257), 27);
+        assert_eq!(length_symbol(258), 28);
+
+        // the distance symbols of DEFLATE
+        assert_eq!(distance_symbols(1 << 15), 30);
+        for (distance, symbol) in &[(1, 0), (4, 3), (5, 4), (7, 5), (24577, 29), (32768, 29)] {
+            assert_eq!(distance_symbol(*distance), *symbol);
+        }
+        assert_eq!(distance_range(29), (24577, 13));
+        assert_eq!(distance_symbol(MAX_WINDOW_SIZE), distance_symbols(MAX_WINDOW_SIZE) - 1);
+    }
+
+    #[test]
+    fn test_find_tokens() {
+        let mut finder = MatchFinder::new();
+        let tokens = finder.find_tokens(b"abcabcabcabcx");
+        assert_eq!(&tokens[3..], &[Token::Match { length: 9, distance: 3 }, Token::Literal(b'x')]);
+
+        // a lazy match gives up "abcd" for the longer "bcdefg"
+        let data = b"abcdxbcdefgyabcdefg";
+        finder.set_lazy_level(0);
+        let tokens = finder.find_tokens(data);
+        assert_eq!(&tokens[10..], &[Token::Match { length: 4, distance: 12 }, Token::Match { length: 3, distance: 8 }]);
+        finder.set_lazy_level(1);
+        let tokens = finder.find_tokens(data);
+        assert_eq!(&tokens[10..], &[Token::Literal(b'a'), Token::Match { length: 6, distance: 8 }]);
+    }
+
+    #[test]
+    fn test_compress_roundtrip() {
+        let text = (0..300).map(|i| format!("GET /static/img{}.png HTTP/1.1 {}\n", i % 17, 200 + i % 3))
+            .collect::<String>();
+        let compressed = compress(text.as_bytes());
+        assert!(compressed.len() < text.len() / 8);
+        assert_eq!(decompress(&compressed).unwrap(), text.as_bytes());
+
+        let mut finder = MatchFinder::new();
+        finder.set_window_size(1 << 20);
+        finder.set_max_chain(8);
+        finder.set_lazy_level(2);
+        let mut data: Vec<u8> = (0..70000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+        data.extend_from_within(..50000);
+        let compressed = finder.compress(&data);
+        assert!(compressed.len() < 75000);
+        assert_eq!(decompress(&compressed).unwrap(), data);
+
+        for data in &[&b""[..], b"a", b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", b"ab"] {
+            assert_eq!(decompress(&compress(data)).unwrap(), *data);
+        }
+    }
+
+    #[test]
+    fn test_invalid_data() {
+        let compressed = compress(b"abcabcabcabcabc");
+        assert!(matches!(decompress(&compressed[..compressed.len() - 1]), Err(Error::UnexpectedEof)));
+
+        let mut bad = compressed.clone();
+        bad[8] = 1;
+        assert!(matches!(decompress(&bad), Err(Error::InvalidHeader(_))));
+
+        let limits = DecodeLimits { max_output: 10, ..DecodeLimits::unlimited() };
+        assert!(matches!(decompress_with_limits(&compressed, &limits), Err(Error::LimitExceeded(_))));
+
+        // a match at the very start has nothing to copy
+        let tokens = [Token::Match { length: 3, distance: 1 }];
+        assert!(matches!(decompress(&encode_tokens(&tokens, 3, 30)), Err(Error::InvalidCode(_))));
+    }
+}
@@ -0,0 +1,274 @@
+//! Coding with several huffman tables, one chosen for every group of
+//! GROUP_SIZE bytes as bzip2 does. This format is not the format of the
+//! HuffmanEncoder. It holds:
+//!
+//! * `u64` little endian: size of the original data
+//! * `u8`: the number of tables
+//! * for every table a `u16` little endian size followed by the serialized ftree
+//! * the table selector of every group, move-to-front transformed and coded
+//!   in unary as in bzip2, padded with 0 bits to a byte boundary
+//! * the coded data
+
+use std::cmp::min;
+use std::io::{self, Write};
+
+use crate::code_book::CodeBook;
+use crate::error::{Error, Result};
+use crate::freq_table::FrequencyTable;
+use crate::freq_tree::{FreqTreeNode, read_trees, write_trees};
+use crate::header::read_orig_size;
+use crate::huffman_decoder::DecodeLimits;
+
+use bitstream::{BitWriter, ReadBits, SliceBitReader};
+
+/// The number of bytes coded with the same table.
+pub const GROUP_SIZE: usize = 50;
+
+/// The largest number of tables an encoding may have.
+pub const MAX_TABLES: usize = 6;
+
+/// The number of passes that refine the tables.
+const REFINE_PASSES: usize = 4;
+
+/// The initial cost of a byte outside of the range of bytes a table starts
+/// out with.
+const UNLIKELY_COST: u32 = 15;
+
+/// An encoder that keeps several huffman tables and picks one for every
+/// group of GROUP_SIZE bytes, like bzip2 does for its blocks. Data whose
+/// statistics change from one part to the next codes shorter than with a
+/// single table for all of it.
+///
+/// The tables start out covering ranges of byte values of about equal total
+/// count. They are then refined with passes like k-means clustering: every
+/// group picks the table that codes it shortest and every table is rebuilt
+/// from the groups that picked it. Every table has a code for every byte of
+/// the data so any table can code any group. The data is decoded by the
+/// MultiTableDecoder.
+pub struct MultiTableEncoder {
+    data: Vec<u8>,
+    max_tables: usize,
+}
+
+impl Default for MultiTableEncoder {
+    fn default() -> MultiTableEncoder {
+        MultiTableEncoder::new()
+    }
+}
+
+impl Write for MultiTableEncoder {
+    /// Appends buf to the data, continuing the current group. Never fails.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.add_chunk(buf);
+        Ok(buf.len())
+    }
+
+    /// Does nothing: the tables are refined over all the data, so no group
+    /// can be coded before encode().
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl MultiTableEncoder {
+    /// Create a new encoder with no data that may use up to MAX_TABLES
+    /// tables.
+    pub fn new() -> MultiTableEncoder {
+        MultiTableEncoder {
+            data: Vec::new(),
+            max_tables: MAX_TABLES,
+        }
+    }
+
+    /// Limit the number of tables. Like bzip2, fewer tables are used for
+    /// short data regardless of the limit.
+    ///
+    /// Panics if max_tables is not between 1 and MAX_TABLES.
+    pub fn set_max_tables(&mut self, max_tables: usize) {
+        assert!((1..=MAX_TABLES).contains(&max_tables), "invalid table limit {}", max_tables);
+        self.max_tables = max_tables;
+    }
+
+    /// Add data to the encoder.
+    pub fn add_chunk(&mut self, chunk: &[u8]) {
+        self.data.extend_from_slice(chunk);
+    }
+
+    /// Returns the table picked for every group, and the number of tables,
+    /// that encode() would use for the data added so far.
+    pub fn table_selectors(&self) -> (Vec<u8>, usize) {
+        let (trees, selectors) = self.build_tables();
+        (selectors, trees.len())
+    }
+
+    /// Encode all data added so far.
+    pub fn encode(&self) -> Vec<u8> {
+        let (trees, selectors) = self.build_tables();
+        let code_books: Vec<CodeBook> = trees.iter()
+            .map(|t| CodeBook::from_tree(t).expect("built trees have valid codes"))
+            .collect();
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&(self.data.len() as u64).to_le_bytes());
+        out.push(trees.len() as u8);
+        write_trees(&mut out, &trees);
+
+        let mut bit_writer = BitWriter::with_writer(&mut out);
+        let mut order: Vec<u8> = (0..trees.len() as u8).collect();
+        let mut bit_count: usize = 0;
+        for selector in &selectors {
+            let pos = order.iter().position(|t| t == selector).unwrap();
+            order[..=pos].rotate_right(1);
+            for _ in 0..pos {
+                bit_writer.add_bit(true).unwrap();
+            }
+            bit_writer.add_bit(false).unwrap();
+            bit_count += pos + 1;
+        }
+        bit_writer.add_bits(0, ((8 - bit_count % 8) % 8) as u8).unwrap();
+
+        for (group, selector) in self.data.chunks(GROUP_SIZE).zip(&selectors) {
+            let code_book = &code_books[*selector as usize];
+            for byte in group {
+                let code = code_book.code(*byte).unwrap();
+                bit_writer.add_bits(code.bits, code.len).unwrap();
+            }
+        }
+        bit_writer.flush().unwrap();
+        drop(bit_writer);
+        out
+    }
+
+    /// Returns the number of tables for the data: as in bzip2, short data
+    /// does not have enough groups to pay for the headers of many tables.
+    fn table_count(&self) -> usize {
+        let count = match self.data.len() {
+            0..=199 => 2,
+            200..=599 => 3,
+            600..=1199 => 4,
+            1200..=2399 => 5,
+            _ => 6,
+        };
+        min(count, self.max_tables)
+    }
+
+    /// Builds the tables and picks the table of every group.
+    fn build_tables(&self) -> (Vec<FreqTreeNode>, Vec<u8>) {
+        let present = FrequencyTable::from_data(&self.data);
+        let table_count = self.table_count();
+
+        // every table starts with a share of the byte values, which it
+        // codes for free
+        let mut costs = vec![[UNLIKELY_COST; 256]; table_count];
+        let mut remaining = present.total();
+        let mut byte = 0;
+        for (t, table_costs) in costs.iter_mut().enumerate() {
+            let share = remaining / (table_count - t) as u64;
+            let mut taken = 0;
+            while byte < 256 && (taken < share || t == table_count - 1) {
+                taken += present.counts()[byte];
+                table_costs[byte] = 0;
+                byte += 1;
+            }
+            remaining -= taken;
+        }
+
+        let mut trees = Vec::new();
+        for _ in 0..REFINE_PASSES {
+            let selectors = pick_tables(&self.data, &costs);
+
+            let mut tables = vec![FrequencyTable::new(); table_count];
+            for (group, selector) in self.data.chunks(GROUP_SIZE).zip(&selectors) {
+                tables[*selector as usize].add_data(group);
+            }
+
+            // every table codes every byte of the data
+            trees = tables.iter_mut().map(|table| {
+                for b in 0..=255u8 {
+                    if present.get(b) > 0 {
+                        table.set(b, table.get(b) + 1);
+                    }
+                }
+                FreqTreeNode::from_frequencies(table)
+            }).collect();
+
+            costs = trees.iter().map(|tree| {
+                let code_book = CodeBook::from_tree(tree).expect("built trees have valid codes");
+                let mut table_costs = [0; 256];
+                for (b, code) in code_book.iter() {
+                    table_costs[b as usize] = code.len as u32;
+                }
+                table_costs
+            }).collect();
+        }
+
+        (trees, pick_tables(&self.data, &costs))
+    }
+}
+
+/// Picks the table with the lowest cost for every group.
+fn pick_tables(data: &[u8], costs: &[[u32; 256]]) -> Vec<u8> {
+    data.chunks(GROUP_SIZE).map(|group| {
+        let group_cost = |t: &usize| group.iter().map(|b| costs[*t][*b as usize]).sum::<u32>();
+        (0..costs.len()).min_by_key(group_cost).unwrap() as u8
+    }).collect()
+}
+
+/// Decoder for data encoded with the MultiTableEncoder.
+pub struct MultiTableDecoder {
+
+}
+
+impl MultiTableDecoder {
+    /// Decodes a buffer encoded with the MultiTableEncoder using the default
+    /// DecodeLimits.
+    pub fn decode(buf: &[u8]) -> Result<Vec<u8>> {
+        Self::decode_with_limits(buf, &DecodeLimits::default())
+    }
+
+    /// Decodes a buffer encoded with the MultiTableEncoder, failing with
+    /// Error::LimitExceeded if the data would exceed any of the limits. The
+    /// node limit applies to each table.
+    pub fn decode_with_limits(buf: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>> {
+        let header = buf.get(..8 + 1).ok_or(Error::UnexpectedEof)?;
+        let orig_size = read_orig_size(buf, limits)?;
+
+        let table_count = header[8] as usize;
+        if !(1..=MAX_TABLES).contains(&table_count) {
+            return Err(Error::InvalidHeader("invalid number of tables"));
+        }
+
+        let (trees, input) = read_trees(&buf[header.len()..], table_count, limits.max_tree_nodes)?;
+
+        let mut breader = SliceBitReader::new(input);
+        let groups = orig_size.div_ceil(GROUP_SIZE as u64);
+        let mut order: Vec<usize> = (0..table_count).collect();
+        let mut selectors = Vec::with_capacity(min(groups, input.len() as u64 * 8) as usize);
+        for _ in 0..groups {
+            let mut pos = 0;
+            while breader.get_bit().ok_or(Error::UnexpectedEof)? {
+                pos += 1;
+                if pos == table_count {
+                    return Err(Error::InvalidHeader("table selector out of range"));
+                }
+            }
+            order[..=pos].rotate_right(1);
+            selectors.push(order[0]);
+        }
+        let data_start = breader.bit_position().div_ceil(8) * 8;
+        breader.set_bit_position(data_start);
+
+        let capacity = min(orig_size, input.len() as u64 * 8) as usize;
+        let mut out = Vec::with_capacity(capacity);
+        for selector in selectors {
+            let tree = &trees[selector];
+            let count = min(GROUP_SIZE as u64, orig_size - out.len() as u64);
+            for _ in 0..count {
+                let (byte, _) = tree.read_symbol(&mut breader).ok_or(Error::UnexpectedEof)?;
+                out.push(byte);
+            }
+        }
+
+        Ok(out)
+    }
+}
@@ -0,0 +1,289 @@
+//! Reading and writing the `.z` files of the classic Unix `pack` utility,
+//! which `unpack` and `pcat` read.
+//!
+//! A packed file starts with the magic bytes `\x1f\x1e`, the original length
+//! as a big endian `u32` and the number of levels of the huffman tree. Then
+//! follows the number of leaves on every level, the last count being stored
+//! minus 2, and the byte values of the leaves level by level. The deepest
+//! leaf of all is an end of file code, which is not listed.
+//!
+//! The codes are not the same as those of the HuffmanEncoder: on every level
+//! the composits take the lowest code values and the leaves follow in the
+//! order they are listed, so the end of file code is all ones.
+
+use std::io::Write;
+
+use crate::canonical::code_lengths;
+use crate::error::{Error, Result};
+use crate::freq_table::histogram;
+use crate::huffman_decoder::DecodeLimits;
+
+use bitstream::{BitWriter, ReadBits, SliceBitReader};
+
+/// The magic bytes every packed file starts with.
+pub const MAGIC: [u8; 2] = [0x1f, 0x1e];
+
+/// The largest number of levels a pack tree may have.
+pub const MAX_LEVELS: u32 = 24;
+
+/// The symbol of the end of file code.
+const EOF_SYMBOL: usize = 256;
+
+/// The tree of a packed file, which is all that is needed to find the byte
+/// value of a code.
+struct PackTree {
+    /// number of leaves on every level, including the end of file code
+    leaves: Vec<usize>,
+    /// number of composits on every level
+    parents: Vec<u64>,
+    /// position in literals of the first leaf of every level
+    lit_base: Vec<usize>,
+    /// byte values of the leaves level by level, without the end of file code
+    literals: Vec<u8>,
+}
+
+impl PackTree {
+    /// Derives the composit counts from the leaf counts, starting at the
+    /// deepest level where every node is a leaf.
+    fn new(leaves: Vec<usize>, literals: Vec<u8>) -> PackTree {
+        let max_len = leaves.len() - 1;
+        let mut parents = vec![0u64; max_len + 1];
+        let mut lit_base = vec![0usize; max_len + 1];
+
+        let mut nodes: u64 = 0;
+        for len in (1..=max_len).rev() {
+            nodes >>= 1;
+            parents[len] = nodes;
+            nodes += leaves[len] as u64;
+        }
+
+        let mut base = 0;
+        for len in 1..=max_len {
+            lit_base[len] = base;
+            base += leaves[len];
+        }
+
+        PackTree { leaves, parents, lit_base, literals }
+    }
+
+    fn max_len(&self) -> usize {
+        self.leaves.len() - 1
+    }
+
+    /// Returns the code of the index-th leaf on a level.
+    fn code(&self, len: usize, index: usize) -> u64 {
+        self.parents[len] + index as u64
+    }
+
+    /// Reads one code and returns its byte value, or None for the end of
+    /// file code.
+    fn read_symbol(&self, reader: &mut SliceBitReader) -> Result<Option<u8>> {
+        let mut code: u64 = 0;
+        for len in 1..=self.max_len() {
+            let bit = reader.get_bit().ok_or(Error::UnexpectedEof)?;
+            code = (code << 1) | bit as u64;
+            if code < self.parents[len] {
+                continue;
+            }
+
+            let index = (code - self.parents[len]) as usize;
+            if index >= self.leaves[len] {
+                return Err(Error::InvalidCode("bits do not match any code"));
+            }
+            if len == self.max_len() && index == self.leaves[len] - 1 {
+                return Ok(None);
+            }
+            return Ok(Some(self.literals[self.lit_base[len] + index]));
+        }
+
+        Err(Error::InvalidCode("bits do not match any code"))
+    }
+}
+
+/// Packs data into the `.z` format. Like `pack` the tree has at most 24
+/// levels, but unlike `pack` empty data and data of a single repeated byte
+/// are packed as well. Data of 4 GiB or more is stored with its length
+/// modulo 2^32, as `pack` does.
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut freqs = histogram(data).to_vec();
+    freqs.push(1);
+    let mut lengths = code_lengths(&freqs, MAX_LEVELS);
+
+    // the end of file code must be on the deepest level; moving it there
+    // costs next to nothing since it is one of the least frequent symbols
+    let max_len = *lengths.iter().max().unwrap();
+    if lengths[EOF_SYMBOL] < max_len {
+        let deepest = lengths.iter().position(|l| *l == max_len).unwrap();
+        lengths.swap(deepest, EOF_SYMBOL);
+    }
+
+    let max_len = max_len as usize;
+    let mut leaves = vec![0usize; max_len + 1];
+    let mut literals = Vec::new();
+    for (len, count) in leaves.iter_mut().enumerate().skip(1) {
+        for byte in 0..=255u8 {
+            if lengths[byte as usize] as usize == len {
+                literals.push(byte);
+                *count += 1;
+            }
+        }
+    }
+    leaves[max_len] += 1;
+    let tree = PackTree::new(leaves, literals);
+
+    let mut out = Vec::with_capacity(data.len() / 2 + 8 + max_len + tree.literals.len());
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.push(max_len as u8);
+    for len in 1..=max_len {
+        let stored = if len == max_len { tree.leaves[len] - 2 } else { tree.leaves[len] };
+        out.push(stored as u8);
+    }
+    out.extend_from_slice(&tree.literals);
+
+    // where in its level every byte value is listed
+    let mut codes = [(0u64, 0u8); 256];
+    for len in 1..=max_len {
+        let count = if len == max_len { tree.leaves[len] - 1 } else { tree.leaves[len] };
+        let level = &tree.literals[tree.lit_base[len]..tree.lit_base[len] + count];
+        for (index, byte) in level.iter().enumerate() {
+            codes[*byte as usize] = (tree.code(len, index), len as u8);
+        }
+    }
+
+    let mut bit_writer = BitWriter::with_writer(&mut out);
+    for byte in data {
+        let (bits, len) = codes[*byte as usize];
+        bit_writer.add_bits(bits, len).unwrap();
+    }
+    bit_writer.add_bits(tree.code(max_len, tree.leaves[max_len] - 1), max_len as u8).unwrap();
+    bit_writer.flush().unwrap();
+    drop(bit_writer);
+
+    out
+}
+
+/// Unpacks a `.z` file using the default DecodeLimits.
+pub fn decode(buf: &[u8]) -> Result<Vec<u8>> {
+    decode_with_limits(buf, &DecodeLimits::default())
+}
+
+/// Unpacks a `.z` file. The original length in the header must match the
+/// unpacked data, modulo 2^32, and is held to the output and ratio limits.
+/// The tree node limit does not apply since a pack tree has at most 513
+/// nodes.
+pub fn decode_with_limits(buf: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>> {
+    if buf.len() < 7 {
+        return Err(Error::UnexpectedEof);
+    }
+    if buf[..2] != MAGIC {
+        return Err(Error::InvalidHeader("not a packed file"));
+    }
+
+    let orig_len = u32::from_be_bytes([buf[2], buf[3], buf[4], buf[5]]) as u64;
+    let (tree, data) = read_tree(&buf[6..])?;
+    limits.check_output(orig_len, buf.len())?;
+
+    let mut out = Vec::with_capacity(orig_len as usize);
+    let mut reader = SliceBitReader::new(data);
+    while let Some(byte) = tree.read_symbol(&mut reader)? {
+        out.push(byte);
+        if out.len() as u64 > orig_len {
+            limits.check_output(out.len() as u64, buf.len())?;
+        }
+    }
+
+    if out.len() as u32 as u64 != orig_len {
+        return Err(Error::InvalidHeader("original length does not match the data"));
+    }
+
+    Ok(out)
+}
+
+/// Reads the level counts and leaves of a pack tree, returning the tree and
+/// the data following it.
+fn read_tree(buf: &[u8]) -> Result<(PackTree, &[u8])> {
+    let max_len = buf[0] as usize;
+    if max_len == 0 || max_len as u32 > MAX_LEVELS {
+        return Err(Error::InvalidHeader("invalid number of levels"));
+    }
+    let counts = buf.get(1..=max_len).ok_or(Error::UnexpectedEof)?;
+
+    // every level can hold two leaves per composit of the level above it,
+    // and the deepest level must also hold the end of file code
+    let mut leaves = vec![0usize; max_len + 1];
+    let mut max_leaves: usize = 1;
+    let mut literal_count = 0;
+    for len in 1..=max_len {
+        leaves[len] = counts[len - 1] as usize;
+        max_leaves *= 2;
+        if leaves[len] + (len == max_len) as usize * 2 > max_leaves {
+            return Err(Error::InvalidHeader("too many leaves in the tree"));
+        }
+        max_leaves -= leaves[len];
+        literal_count += leaves[len];
+    }
+    leaves[max_len] += 1;
+    literal_count += 1;
+    if literal_count > 256 {
+        return Err(Error::InvalidHeader("too many leaves in the tree"));
+    }
+
+    let rest = &buf[1 + max_len..];
+    let literals = rest.get(..literal_count).ok_or(Error::UnexpectedEof)?.to_vec();
+    leaves[max_len] += 1;
+
+    Ok((PackTree::new(leaves, literals), &rest[literal_count..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_file() {
+        // unpacked with gzip -d, which reads pack files
+        let packed = [
+            0x1f, 0x1e, 0x00, 0x00, 0x00, 0x1a, 0x05, 0x00, 0x00, 0x04, 0x05, 0x04,
+            0x20, 0x65, 0x6c, 0x6f, 0x0a, 0x64, 0x68, 0x72, 0x77, 0x2c, 0x61, 0x63,
+            0x6b, 0x70, 0x5b, 0xb7, 0x8b, 0x76, 0xf1, 0xfb, 0x64, 0x04, 0x20, 0x44,
+            0x3a, 0x86, 0x50,
+        ];
+        let data = b"hello hello world, packed\n";
+
+        assert_eq!(encode(data), &packed[..]);
+        assert_eq!(decode(&packed).unwrap(), &data[..]);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut skewed = Vec::new();
+        let (mut a, mut b) = (1usize, 1usize);
+        for byte in 0..32u8 {
+            skewed.extend(std::iter::repeat_n(byte, a));
+            let next = a + b;
+            a = b;
+            b = next;
+        }
+
+        for data in &[&b""[..], b"a", b"aaaaaaa", &skewed] {
+            let packed = encode(data);
+            assert!(packed[6] as u32 <= MAX_LEVELS);
+            assert_eq!(decode_with_limits(&packed, &DecodeLimits::unlimited()).unwrap(), *data);
+        }
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let packed = encode(b"some data to pack");
+        assert!(matches!(decode(&packed[..packed.len() - 1]), Err(Error::UnexpectedEof)));
+        assert!(matches!(decode(b"\x1f\x8b\0\0\0\0\0"), Err(Error::InvalidHeader(_))));
+
+        let mut wrong_len = packed.clone();
+        wrong_len[5] += 1;
+        assert!(matches!(decode(&wrong_len), Err(Error::InvalidHeader(_))));
+
+        // a single level cannot hold three leaves
+        assert!(matches!(decode(b"\x1f\x1e\0\0\0\x01\x01\x01ab\xff"), Err(Error::InvalidHeader(_))));
+    }
+}
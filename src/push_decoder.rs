@@ -0,0 +1,166 @@
+use std::cmp::min;
+
+use crate::error::Result;
+use crate::freq_tree::{FreqTreeNode, FreqNodeData};
+use crate::header::Header;
+use crate::huffman_decoder::DecodeLimits;
+
+/// The state of a PushDecoder or PushStreamDecoder after a call to feed().
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeStatus {
+    /// All input has been used and more is needed to finish decoding.
+    NeedMoreInput,
+    /// All of the original data has been decoded. Any further input is
+    /// ignored.
+    Done,
+}
+
+/// A node of the huffman tree flattened into an array, so that the decoder can
+/// keep its position inside the tree between calls.
+#[derive(Clone, Copy)]
+enum FlatNode {
+    Composit { left: usize, right: usize },
+    Value(u8),
+}
+
+/// A decoder that is handed the encoded data piece by piece instead of
+/// reading it, for use in event loops that cannot block. Pieces may be split
+/// at any byte, including in the middle of the header or of a code, and the
+/// decoder picks up where it left off on the next call.
+///
+/// Since the total size of the encoded data is not known up front, only the
+/// output size, tree node and header size limits of the DecodeLimits are
+/// enforced.
+pub struct PushDecoder {
+    limits: DecodeLimits,
+    header_buf: Vec<u8>,
+    tree: Vec<FlatNode>,
+    remaining: u64,
+    at: usize,
+    done: bool,
+}
+
+impl PushDecoder {
+    /// Create a new decoder using the default DecodeLimits.
+    pub fn new() -> PushDecoder {
+        Self::with_limits(DecodeLimits::default())
+    }
+
+    /// Create a new decoder using the given limits.
+    pub fn with_limits(limits: DecodeLimits) -> PushDecoder {
+        PushDecoder {
+            limits,
+            header_buf: Vec::new(),
+            tree: Vec::new(),
+            remaining: 0,
+            at: 0,
+            done: false,
+        }
+    }
+
+    /// Decodes as much of input as possible, appending the decoded bytes to out.
+    pub fn feed(&mut self, mut input: &[u8], out: &mut Vec<u8>) -> Result<DecodeStatus> {
+        if self.tree.is_empty() {
+            input = self.feed_header(input)?;
+            if self.tree.is_empty() {
+                return Ok(DecodeStatus::NeedMoreInput);
+            }
+        }
+
+        for byte in input {
+            if self.done {
+                break;
+            }
+
+            for i in (0..8).rev() {
+                let bit = (byte >> i) & 1 != 0;
+                if let FlatNode::Composit { left, right } = self.tree[self.at] {
+                    self.at = if bit { right } else { left };
+                }
+
+                if let FlatNode::Value(v) = self.tree[self.at] {
+                    out.push(v);
+                    self.at = 0;
+                    self.remaining -= 1;
+                    if self.remaining == 0 {
+                        self.done = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(self.status())
+    }
+
+    /// Returns true once all of the original data has been decoded.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn status(&self) -> DecodeStatus {
+        if self.done {
+            DecodeStatus::Done
+        } else {
+            DecodeStatus::NeedMoreInput
+        }
+    }
+
+    /// Buffers header bytes until the whole header is available and parses it.
+    /// Only the bytes the header needs are buffered, and its size is checked
+    /// against the limits as soon as it is known. Returns the part of input
+    /// following the header.
+    fn feed_header<'i>(&mut self, mut input: &'i [u8]) -> Result<&'i [u8]> {
+        let header_len = loop {
+            let needed = Header::needed_len(&self.header_buf, &self.limits)?;
+            if self.header_buf.len() >= needed {
+                break needed;
+            }
+            if input.is_empty() {
+                return Ok(input);
+            }
+            let take = min(needed - self.header_buf.len(), input.len());
+            self.header_buf.extend_from_slice(&input[..take]);
+            input = &input[take..];
+        };
+
+        let (header, _) = Header::parse(&self.header_buf[..header_len], &self.limits)?;
+        self.limits.check_output(header.orig_size, usize::MAX)?;
+
+        self.tree = flatten(&header.ftree);
+        self.remaining = header.orig_size;
+        self.done = header.orig_size == 0;
+        self.header_buf = Vec::new();
+
+        Ok(input)
+    }
+}
+
+impl Default for PushDecoder {
+    fn default() -> PushDecoder {
+        PushDecoder::new()
+    }
+}
+
+/// Flattens a tree into an array with the root at index 0.
+fn flatten(tree: &FreqTreeNode) -> Vec<FlatNode> {
+    let mut flat = vec![FlatNode::Value(0)];
+    let mut stack = vec![(tree, 0)];
+
+    while let Some((node, idx)) = stack.pop() {
+        match &node.data {
+            FreqNodeData::Composit(c) => {
+                let left = flat.len();
+                let right = left + 1;
+                flat.push(FlatNode::Value(0));
+                flat.push(FlatNode::Value(0));
+                flat[idx] = FlatNode::Composit { left, right };
+                stack.push((c.left.as_ref(), left));
+                stack.push((c.right.as_ref(), right));
+            },
+            FreqNodeData::Value(v) => flat[idx] = FlatNode::Value(v.byte_val),
+        }
+    }
+
+    flat
+}
@@ -0,0 +1,208 @@
+use crate::canonical::{CanonicalCode, PartialCode};
+use crate::error::{Error, Result};
+use crate::freq_table::FrequencyTable;
+use crate::push_decoder::DecodeStatus;
+
+/// The symbol written by flush(), after which the stream continues at the
+/// next byte boundary.
+const FLUSH_SYMBOL: usize = 256;
+
+/// The symbol written by finish(), which ends the stream.
+const END_SYMBOL: usize = 257;
+
+/// The byte values plus the flush and end symbols.
+const ALPHABET_LEN: usize = 258;
+
+/// The longest code of a stream, short enough to store every code length in
+/// four bits.
+const MAX_STREAM_CODE_LEN: u32 = 15;
+
+/// The size of the stream header, which holds the code length of every
+/// symbol in four bits, high bits first.
+const STREAM_HEADER_LEN: usize = ALPHABET_LEN / 2;
+
+/// An encoder that is handed its input piece by piece and hands back the
+/// encoded bytes as soon as they are complete, for use in event loops that
+/// cannot hold all of the data. Since the data is not known up front the
+/// code comes from a fixed or pre-trained table instead.
+///
+/// The stream it produces is not the format of the HuffmanEncoder. It starts
+/// with a header of the code lengths and has no original size; instead the
+/// byte values are coded together with two extra symbols. flush() writes a
+/// flush symbol followed by zero bits up to the next byte boundary, so that
+/// the receiver can decode everything up to it, and finish() writes an end
+/// symbol the same way. The stream is decoded by a PushStreamDecoder.
+pub struct PushEncoder {
+    code: CanonicalCode,
+    header_written: bool,
+    bits: u64,
+    bit_count: u32,
+}
+
+impl PushEncoder {
+    /// Create a new encoder with a fixed code that spends 8 or 9 bits on
+    /// every byte, for data that nothing is known about.
+    pub fn new() -> PushEncoder {
+        Self::with_frequencies(&FrequencyTable::from([1u64; 256]))
+    }
+
+    /// Create a new encoder with a code built from a table trained on
+    /// similar data. Bytes with a count of 0 in the table still get a code.
+    pub fn with_frequencies(table: &FrequencyTable) -> PushEncoder {
+        let mut freqs: Vec<u64> = table.counts().iter().map(|c| std::cmp::max(1, *c)).collect();
+        freqs.push(1);
+        freqs.push(1);
+
+        PushEncoder {
+            code: CanonicalCode::from_frequencies(&freqs, MAX_STREAM_CODE_LEN),
+            header_written: false,
+            bits: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Returns the code used for the byte values and the flush and end
+    /// symbols, which are symbols 256 and 257.
+    pub fn code(&self) -> &CanonicalCode {
+        &self.code
+    }
+
+    /// Encodes input and appends all bytes that are complete to out. The
+    /// last few bits are held back until more input follows or the stream is
+    /// flushed.
+    pub fn push(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        self.write_header(out);
+        for byte in input {
+            self.write_symbol(*byte as usize, out);
+        }
+    }
+
+    /// Writes a flush marker and pads to the next byte boundary, so that out
+    /// holds the encoding of all input pushed so far.
+    pub fn flush(&mut self, out: &mut Vec<u8>) {
+        self.write_header(out);
+        self.write_symbol(FLUSH_SYMBOL, out);
+        self.pad(out);
+    }
+
+    /// Writes the end of the stream to out.
+    pub fn finish(mut self, out: &mut Vec<u8>) {
+        self.write_header(out);
+        self.write_symbol(END_SYMBOL, out);
+        self.pad(out);
+    }
+
+    fn write_header(&mut self, out: &mut Vec<u8>) {
+        if self.header_written {
+            return;
+        }
+
+        out.extend(self.code.lengths().chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+        self.header_written = true;
+    }
+
+    fn write_symbol(&mut self, symbol: usize, out: &mut Vec<u8>) {
+        let code = self.code.code(symbol).unwrap();
+        self.bits = (self.bits << code.len) | code.bits;
+        self.bit_count += code.len as u32;
+
+        while self.bit_count >= 8 {
+            self.bit_count -= 8;
+            out.push((self.bits >> self.bit_count) as u8);
+        }
+    }
+
+    fn pad(&mut self, out: &mut Vec<u8>) {
+        if self.bit_count > 0 {
+            out.push((self.bits << (8 - self.bit_count)) as u8);
+        }
+        self.bits = 0;
+        self.bit_count = 0;
+    }
+}
+
+impl Default for PushEncoder {
+    fn default() -> PushEncoder {
+        PushEncoder::new()
+    }
+}
+
+/// Decodes the streams of a PushEncoder, being handed the stream in pieces
+/// split at any byte. Everything up to a flush marker is decoded as soon as
+/// the marker has been fed.
+pub struct PushStreamDecoder {
+    header_buf: Vec<u8>,
+    code: Option<CanonicalCode>,
+    partial: PartialCode,
+    done: bool,
+}
+
+impl PushStreamDecoder {
+    /// Create a new decoder for a single stream.
+    pub fn new() -> PushStreamDecoder {
+        PushStreamDecoder {
+            header_buf: Vec::new(),
+            code: None,
+            partial: PartialCode::default(),
+            done: false,
+        }
+    }
+
+    /// Decodes as much of input as possible, appending the decoded bytes to
+    /// out. Returns DecodeStatus::Done once the end of the stream is reached.
+    pub fn feed(&mut self, mut input: &[u8], out: &mut Vec<u8>) -> Result<DecodeStatus> {
+        if self.code.is_none() {
+            let needed = STREAM_HEADER_LEN - self.header_buf.len();
+            let take = std::cmp::min(needed, input.len());
+            self.header_buf.extend_from_slice(&input[..take]);
+            input = &input[take..];
+            if self.header_buf.len() < STREAM_HEADER_LEN {
+                return Ok(DecodeStatus::NeedMoreInput);
+            }
+            self.code = Some(read_header(&self.header_buf)?);
+            self.header_buf = Vec::new();
+        }
+
+        let code = self.code.as_ref().unwrap();
+        for byte in input {
+            if self.done {
+                break;
+            }
+
+            for i in (0..8).rev() {
+                match code.next_bit(&mut self.partial, (byte >> i) & 1 != 0)? {
+                    Some(FLUSH_SYMBOL) => break,
+                    Some(END_SYMBOL) => {
+                        self.done = true;
+                        break;
+                    },
+                    Some(symbol) => out.push(symbol as u8),
+                    None => (),
+                }
+            }
+        }
+
+        Ok(if self.done { DecodeStatus::Done } else { DecodeStatus::NeedMoreInput })
+    }
+
+    /// Returns true once the end of the stream has been decoded.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+}
+
+impl Default for PushStreamDecoder {
+    fn default() -> PushStreamDecoder {
+        PushStreamDecoder::new()
+    }
+}
+
+/// Reads the code lengths of the stream header. The flush and end symbols
+/// must have a code.
+fn read_header(buf: &[u8]) -> Result<CanonicalCode> {
+    let lengths: Vec<u8> = buf.iter().flat_map(|b| [b >> 4, b & 0x0f]).collect();
+    if lengths[FLUSH_SYMBOL] == 0 || lengths[END_SYMBOL] == 0 {
+        return Err(Error::InvalidHeader("stream has no flush or end code"));
+    }
+    CanonicalCode::from_lengths(&lengths)
+}
@@ -0,0 +1,289 @@
+//! Huffman coding with a run-length pre-pass. This format is not the format
+//! of the HuffmanEncoder. It holds:
+//!
+//! * `u64` little endian: size of the original data
+//! * the code length of every symbol, four bits each with the high bits
+//!   first, padded with 0 bits to a byte boundary
+//! * the coded data, padded with 0 bits to a byte boundary
+//!
+//! The symbols are the 256 byte values, an escape symbol 256 that starts a
+//! run and 40 length symbols. Length symbol k follows an escape and is
+//! followed by k extra bits, high bits first, that complete a repeat count
+//! from 2^k to 2^(k+1) - 1 of the byte before the escape.
+
+use std::cmp::min;
+use std::io::{self, Write};
+
+use crate::canonical::{CanonicalCode, MAX_PACKED_CODE_LEN, code_lengths, read_packed_lengths, write_packed_lengths};
+use crate::error::{Error, Result};
+use crate::header::read_orig_size;
+use crate::huffman_decoder::DecodeLimits;
+use crate::huffman_encoder::HuffmanEncoder;
+
+use bitstream::{BitWriter, ReadBits, SliceBitReader};
+
+/// The symbol that starts a run, after the byte values.
+const ESCAPE_SYMBOL: usize = 256;
+
+/// The number of run length symbols. Symbol k stands for the repeat counts
+/// from 2^k to 2^(k+1) - 1 and is followed by k extra bits.
+const LENGTH_SYMBOLS: usize = 40;
+
+/// The byte values, the escape symbol and the run length symbols.
+const ALPHABET_LEN: usize = ESCAPE_SYMBOL + 1 + LENGTH_SYMBOLS;
+
+/// The largest repeat count of a single run, longer runs take several.
+const MAX_REPEAT: u64 = (1 << LENGTH_SYMBOLS) - 1;
+
+/// The run length used by RleEncoder::new().
+pub const DEFAULT_MIN_RUN: usize = 8;
+
+/// The output limit of RleDecoder::decode().
+pub const DEFAULT_MAX_OUTPUT: u64 = 1 << 32;
+
+/// What the run-length pre-pass of an RleEncoder did to its data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RleStats {
+    /// The number of runs replaced by an escape and a length.
+    pub runs: u64,
+    /// The number of bytes in those runs.
+    pub run_bytes: u64,
+    /// The number of symbols coded, bytes and escapes and lengths.
+    pub symbols: u64,
+    /// The size of the output of encode().
+    pub encoded_len: usize,
+    /// The size of the output of the HuffmanEncoder for the same data.
+    pub huffman_len: usize,
+}
+
+impl RleStats {
+    /// Returns the number of bytes the run-length pre-pass saved compared to
+    /// the HuffmanEncoder, negative if it made the output larger.
+    pub fn saved(&self) -> i64 {
+        self.huffman_len as i64 - self.encoded_len as i64
+    }
+}
+
+/// A byte or the repetition of the byte before it.
+#[derive(Clone, Copy)]
+enum RleToken {
+    Byte(u8),
+    Repeat(u64),
+}
+
+/// An encoder that replaces long runs of the same byte before huffman coding
+/// them. A byte costs at least one bit in the HuffmanEncoder, so long runs,
+/// like the zero fill of sensor dumps, stay large there.
+///
+/// The first byte of a run of at least the minimum run length is coded
+/// normally and the rest of the run becomes an escape symbol followed by a
+/// length symbol and extra bits that give the repeat count. The escape and
+/// length symbols extend the alphabet of the byte values, and all of them
+/// share a code built from their counts. The data is decoded by the
+/// RleDecoder.
+pub struct RleEncoder {
+    data: Vec<u8>,
+    min_run: usize,
+}
+
+impl Default for RleEncoder {
+    fn default() -> RleEncoder {
+        RleEncoder::new()
+    }
+}
+
+impl Write for RleEncoder {
+    /// Adds buf to the encoder, a run may continue from one write to the
+    /// next. Never fails.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.add_chunk(buf);
+        Ok(buf.len())
+    }
+
+    /// Does nothing: the code is built from all the data, so no run can be
+    /// coded before encode().
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl RleEncoder {
+    /// Create a new encoder with no data that replaces runs of at least
+    /// DEFAULT_MIN_RUN bytes.
+    pub fn new() -> RleEncoder {
+        RleEncoder {
+            data: Vec::new(),
+            min_run: DEFAULT_MIN_RUN,
+        }
+    }
+
+    /// Set the shortest run that is replaced.
+    ///
+    /// Panics if min_run is less than 2.
+    pub fn set_min_run(&mut self, min_run: usize) {
+        assert!(min_run >= 2, "a run needs at least 2 bytes");
+        self.min_run = min_run;
+    }
+
+    /// Add data to the encoder. Runs continue across chunks.
+    pub fn add_chunk(&mut self, chunk: &[u8]) {
+        self.data.extend_from_slice(chunk);
+    }
+
+    /// Encode all data added so far.
+    pub fn encode(&self) -> Vec<u8> {
+        self.encode_tokens(&self.tokens())
+    }
+
+    /// Returns what encode() would save for the data added so far. This
+    /// encodes the data both with runs replaced and with the HuffmanEncoder.
+    pub fn stats(&self) -> RleStats {
+        let tokens = self.tokens();
+        let mut huffman = HuffmanEncoder::new();
+        huffman.add_chunk_ref(&self.data);
+        let mut stats = RleStats {
+            runs: 0,
+            run_bytes: 0,
+            symbols: 0,
+            encoded_len: self.encode_tokens(&tokens).len(),
+            huffman_len: huffman.encoded_len(),
+        };
+
+        let mut previous_repeat = false;
+        for token in &tokens {
+            match token {
+                RleToken::Byte(_) => {
+                    stats.symbols += 1;
+                    previous_repeat = false;
+                },
+                RleToken::Repeat(count) => {
+                    // the byte before the first repeat starts the run
+                    if !previous_repeat {
+                        stats.runs += 1;
+                        stats.run_bytes += 1;
+                    }
+                    stats.symbols += 2;
+                    stats.run_bytes += count;
+                    previous_repeat = true;
+                },
+            }
+        }
+        stats
+    }
+
+    fn tokens(&self) -> Vec<RleToken> {
+        let mut tokens = Vec::new();
+        let mut rest = &self.data[..];
+        while let Some(byte) = rest.first() {
+            let run = rest.iter().take_while(|b| *b == byte).count();
+            if run < self.min_run {
+                tokens.extend(std::iter::repeat_n(RleToken::Byte(*byte), run));
+            } else {
+                tokens.push(RleToken::Byte(*byte));
+                let mut repeat = run as u64 - 1;
+                while repeat > 0 {
+                    let count = min(repeat, MAX_REPEAT);
+                    tokens.push(RleToken::Repeat(count));
+                    repeat -= count;
+                }
+            }
+            rest = &rest[run..];
+        }
+        tokens
+    }
+
+    fn encode_tokens(&self, tokens: &[RleToken]) -> Vec<u8> {
+        let mut freqs = vec![0u64; ALPHABET_LEN];
+        for token in tokens {
+            match token {
+                RleToken::Byte(b) => freqs[*b as usize] += 1,
+                RleToken::Repeat(count) => {
+                    freqs[ESCAPE_SYMBOL] += 1;
+                    freqs[length_symbol(*count)] += 1;
+                },
+            }
+        }
+        let lengths = code_lengths(&freqs, MAX_PACKED_CODE_LEN);
+        let code = CanonicalCode::from_lengths(&lengths).expect("computed lengths form a code");
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&(self.data.len() as u64).to_le_bytes());
+        write_packed_lengths(&mut out, &lengths);
+
+        let mut bit_writer = BitWriter::with_writer(&mut out);
+        for token in tokens {
+            match token {
+                RleToken::Byte(b) => code.write_symbol(&mut bit_writer, *b as usize).unwrap(),
+                RleToken::Repeat(count) => {
+                    let symbol = length_symbol(*count);
+                    let extra = (symbol - ESCAPE_SYMBOL - 1) as u8;
+                    code.write_symbol(&mut bit_writer, ESCAPE_SYMBOL).unwrap();
+                    code.write_symbol(&mut bit_writer, symbol).unwrap();
+                    bit_writer.add_bits(*count - (1 << extra), extra).unwrap();
+                },
+            }
+        }
+        bit_writer.flush().unwrap();
+        drop(bit_writer);
+        out
+    }
+}
+
+/// The length symbol of a repeat count.
+fn length_symbol(count: u64) -> usize {
+    ESCAPE_SYMBOL + 1 + (63 - count.leading_zeros()) as usize
+}
+
+/// Decoder for data encoded with the RleEncoder.
+pub struct RleDecoder {
+
+}
+
+impl RleDecoder {
+    /// Decodes a buffer encoded with the RleEncoder with an output limit of
+    /// 4 GiB and no ratio limit, as runs compress far beyond any ratio.
+    /// Larger data has to be decoded with decode_with_limits().
+    pub fn decode(buf: &[u8]) -> Result<Vec<u8>> {
+        let limits = DecodeLimits { max_output: DEFAULT_MAX_OUTPUT, ..DecodeLimits::unlimited() };
+        Self::decode_with_limits(buf, &limits)
+    }
+
+    /// Decodes a buffer encoded with the RleEncoder, failing with
+    /// Error::LimitExceeded if the data would exceed the output or ratio
+    /// limit.
+    pub fn decode_with_limits(buf: &[u8], limits: &DecodeLimits) -> Result<Vec<u8>> {
+        let orig_size = read_orig_size(buf, limits)?;
+        let (lengths, input) = read_packed_lengths(&buf[8..], ALPHABET_LEN)?;
+        let code = CanonicalCode::from_lengths(&lengths)?;
+
+        let mut reader = SliceBitReader::new(input);
+        let mut out = Vec::with_capacity(min(orig_size, input.len() as u64 * 8) as usize);
+        while (out.len() as u64) < orig_size {
+            let symbol = code.read_symbol(&mut reader)?;
+            if symbol < ESCAPE_SYMBOL {
+                out.push(symbol as u8);
+                continue;
+            }
+            if symbol != ESCAPE_SYMBOL {
+                return Err(Error::InvalidCode("run length without an escape"));
+            }
+
+            let byte = *out.last().ok_or(Error::InvalidCode("run before the first byte"))?;
+            let symbol = code.read_symbol(&mut reader)?;
+            if symbol <= ESCAPE_SYMBOL {
+                return Err(Error::InvalidCode("escape without a run length"));
+            }
+            let extra = (symbol - ESCAPE_SYMBOL - 1) as u8;
+            let mut count: u64 = 1;
+            for _ in 0..extra {
+                count = (count << 1) | reader.get_bit().ok_or(Error::UnexpectedEof)? as u64;
+            }
+            if count > orig_size - out.len() as u64 {
+                return Err(Error::InvalidCode("run past the end of the data"));
+            }
+            out.resize(out.len() + count as usize, byte);
+        }
+
+        Ok(out)
+    }
+}
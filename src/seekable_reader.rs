@@ -0,0 +1,159 @@
+use std::cmp::min;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::error::{Error, Result};
+use crate::freq_tree::FreqTreeNode;
+use crate::header::Header;
+use crate::huffman_decoder::DecodeLimits;
+
+use bitstream::BitReader;
+
+/// A position in the huffman coded data at which decoding can start without
+/// decoding any of the data before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyncPoint {
+    /// Offset into the original, uncompressed data.
+    pub offset: u64,
+    /// Offset in bits from the start of the huffman coded data.
+    pub bit_offset: u64,
+}
+
+/// A reader over data produced by the HuffmanEncoder that supports seeking to
+/// any offset of the original data. Seeking uses the sync point index written
+/// by an encoder configured with set_sync_interval() to jump to the closest
+/// preceding sync point and only decodes the data between it and the target.
+/// Without an index every seek decodes from the start of the data.
+///
+/// Every call to read() seeks the underlying source to the current position,
+/// so the source may be shared with other users between calls.
+pub struct SeekableHuffmanReader<R: Read + Seek> {
+    source: R,
+    ftree: FreqTreeNode,
+    orig_size: u64,
+    data_start: u64,
+    sync_points: Vec<SyncPoint>,
+    pos: u64,
+    cursor: SyncPoint,
+}
+
+impl<R: Read + Seek> SeekableHuffmanReader<R> {
+    /// Create a new reader by parsing the header at the current position of
+    /// source. Fails with Error::InvalidHeader if a sync point lies past the
+    /// end of the source.
+    pub fn new(mut source: R) -> Result<SeekableHuffmanReader<R>> {
+        let header = Header::read_from(&mut source, &DecodeLimits::default())?;
+        let data_start = source.stream_position()?;
+        let data_len = source.seek(SeekFrom::End(0))?.saturating_sub(data_start);
+        source.seek(SeekFrom::Start(data_start))?;
+
+        if header.sync_points.iter().any(|p| p.bit_offset > data_len.saturating_mul(8)) {
+            return Err(Error::InvalidHeader("sync point past end of data"));
+        }
+
+        Ok(SeekableHuffmanReader {
+            source,
+            ftree: header.ftree,
+            orig_size: header.orig_size,
+            data_start,
+            sync_points: header.sync_points,
+            pos: 0,
+            cursor: SyncPoint { offset: 0, bit_offset: 0 },
+        })
+    }
+
+    /// Returns the size of the original, uncompressed data.
+    pub fn original_size(&self) -> u64 {
+        self.orig_size
+    }
+
+    /// Returns the sync point index stored in the header. The start of the
+    /// data is always an implicit sync point and is not included.
+    pub fn sync_points(&self) -> &[SyncPoint] {
+        &self.sync_points
+    }
+
+    /// Consumes the reader and returns the underlying source.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    fn nearest_sync_point(&self, offset: u64) -> SyncPoint {
+        let idx = self.sync_points.partition_point(|p| p.offset <= offset);
+        if idx == 0 {
+            SyncPoint { offset: 0, bit_offset: 0 }
+        } else {
+            self.sync_points[idx - 1]
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableHuffmanReader<R> {
+    /// Decodes original data starting at the current position. Any data
+    /// between the last decoded position or sync point and the current
+    /// position is decoded and discarded first.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.orig_size {
+            return Ok(0);
+        }
+
+        let start = self.data_start.checked_add(self.cursor.bit_offset / 8)
+            .ok_or(Error::InvalidHeader("sync point past end of data"))?;
+        self.source.seek(SeekFrom::Start(start))?;
+        let mut breader = BitReader::with_reader(&mut self.source);
+
+        for _ in 0..self.cursor.bit_offset % 8 {
+            breader.get_bit().ok_or(Error::UnexpectedEof)?;
+        }
+
+        while self.cursor.offset < self.pos {
+            let (_, bits) = self.ftree.read_symbol(&mut breader).ok_or(Error::UnexpectedEof)?;
+            self.cursor.offset += 1;
+            self.cursor.bit_offset += bits;
+        }
+
+        let amount = min(buf.len() as u64, self.orig_size - self.pos) as usize;
+        for slot in &mut buf[..amount] {
+            let (byte, bits) = self.ftree.read_symbol(&mut breader).ok_or(Error::UnexpectedEof)?;
+            *slot = byte;
+            self.cursor.offset += 1;
+            self.cursor.bit_offset += bits;
+        }
+
+        self.pos = self.cursor.offset;
+        Ok(amount)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableHuffmanReader<R> {
+    /// Seeks to an offset in the original data. Seeking beyond the end is
+    /// allowed, reads from there return 0 bytes.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => add_offset(self.orig_size, x),
+            SeekFrom::Current(x) => add_offset(self.pos, x),
+        };
+
+        let target = target.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position")
+        })?;
+
+        // Keep decoding from the current cursor if it is closer to the target
+        // than any sync point.
+        let nearest = self.nearest_sync_point(target);
+        if self.cursor.offset > target || self.cursor.offset < nearest.offset {
+            self.cursor = nearest;
+        }
+
+        self.pos = target;
+        Ok(target)
+    }
+}
+
+fn add_offset(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.unsigned_abs())
+    }
+}
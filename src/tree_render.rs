@@ -0,0 +1,177 @@
+use std::fmt::{self, Write};
+
+use crate::freq_table::FrequencyTable;
+use crate::freq_tree::{FreqTreeNode, FreqNodeData};
+
+impl FreqTreeNode {
+    /// Renders the tree as a Graphviz DOT digraph. Composit nodes are labeled
+    /// with their weight and leaves with their byte value, weight and code.
+    /// Edges are labeled with the bit they stand for. Weights are left out if
+    /// the tree has none, as is the case for trees read back from a header.
+    pub fn to_dot(&self) -> String {
+        let weighted = self.get_weight() > 0;
+        let mut dot = String::from("digraph huffman {\n    node [fontname=\"monospace\"];\n");
+        let mut next_id = 0;
+        let mut stack = vec![(self, None, String::new())];
+
+        while let Some((node, parent, code)) = stack.pop() {
+            let id = next_id;
+            next_id += 1;
+
+            match &node.data {
+                FreqNodeData::Composit(c) => {
+                    let label = if weighted { c.occures.to_string() } else { String::new() };
+                    writeln!(dot, "    n{} [shape=circle, label=\"{}\"];", id, label).unwrap();
+                    stack.push((c.right.as_ref(), Some((id, '1')), code.clone() + "1"));
+                    stack.push((c.left.as_ref(), Some((id, '0')), code + "0"));
+                },
+                FreqNodeData::Value(v) => {
+                    let mut label = escape_byte(v.byte_val).replace('\\', "\\\\").replace('"', "\\\"");
+                    if weighted {
+                        write!(label, "\\n{}", v.occures).unwrap();
+                    }
+                    write!(label, "\\n{}", code).unwrap();
+                    writeln!(dot, "    n{} [shape=box, label=\"{}\"];", id, label).unwrap();
+                }
+            }
+
+            if let Some((parent_id, bit)) = parent {
+                writeln!(dot, "    n{} -> n{} [label=\"{}\"];", parent_id, id, bit).unwrap();
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Renders the tree as indented text, one node per line. Composit nodes
+    /// show their weight and leaves show their byte value, weight and code.
+    /// Weights are left out if the tree has none.
+    ///
+    /// ```text
+    /// * (7)
+    /// +-0- * (3)
+    /// |    +-0- 'c' (1) 00
+    /// |    `-1- 'b' (2) 01
+    /// `-1- 'a' (4) 1
+    /// ```
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        self.write_text(&mut text, self.get_weight() > 0, "", "", &mut String::new()).unwrap();
+        text
+    }
+
+    /// Sets the weight of every leaf to the count of its byte in the table and
+    /// recomputes the weights of all composit nodes. This allows a tree read
+    /// back from a header to be rendered with the frequencies of the decoded
+    /// data.
+    pub fn assign_weights(&mut self, table: &FrequencyTable) {
+        match &mut self.data {
+            FreqNodeData::Composit(c) => {
+                c.left.assign_weights(table);
+                c.right.assign_weights(table);
+                c.occures = c.left.get_weight().saturating_add(c.right.get_weight());
+            },
+            FreqNodeData::Value(v) => v.occures = table.get(v.byte_val),
+        }
+    }
+
+    fn write_text(&self, out: &mut String, weighted: bool, prefix: &str, child_prefix: &str,
+                  code: &mut String) -> fmt::Result {
+        out.push_str(prefix);
+
+        match &self.data {
+            FreqNodeData::Composit(c) => {
+                out.push('*');
+                if weighted {
+                    write!(out, " ({})", c.occures)?;
+                }
+                out.push('\n');
+
+                code.push('0');
+                c.left.write_text(out, weighted, &format!("{}+-0- ", child_prefix),
+                                  &format!("{}|    ", child_prefix), code)?;
+                code.pop();
+
+                code.push('1');
+                c.right.write_text(out, weighted, &format!("{}`-1- ", child_prefix),
+                                   &format!("{}     ", child_prefix), code)?;
+                code.pop();
+            },
+            FreqNodeData::Value(v) => {
+                write!(out, "'{}'", escape_byte(v.byte_val))?;
+                if weighted {
+                    write!(out, " ({})", v.occures)?;
+                }
+                writeln!(out, " {}", code)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for FreqTreeNode {
+    /// Formats the tree like to_text()
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_text())
+    }
+}
+
+/// Escapes a byte for display, non-printable bytes are shown as \xNN.
+fn escape_byte(byte: u8) -> String {
+    std::ascii::escape_default(byte).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_tree() -> FreqTreeNode {
+        FreqTreeNode::from_frequencies(&FrequencyTable::from_data(b"aaaabbc"))
+    }
+
+    #[test]
+    fn tree_to_text() {
+        let expected = "\
+* (7)
++-0- * (3)
+|    +-0- 'c' (1) 00
+|    `-1- 'b' (2) 01
+`-1- 'a' (4) 1
+";
+        assert_eq!(sample_tree().to_text(), expected);
+        assert_eq!(sample_tree().to_string(), expected);
+    }
+
+    #[test]
+    fn tree_to_text_unweighted() {
+        let tree = FreqTreeNode::composit(FreqTreeNode::leaf(b'\n', 0), FreqTreeNode::leaf(0xff, 0));
+        assert_eq!(tree.to_text(), "*\n+-0- '\\n' 0\n`-1- '\\xff' 1\n");
+    }
+
+    #[test]
+    fn tree_assign_weights() {
+        let mut tree = FreqTreeNode::decode(&sample_tree().encode()).unwrap();
+        assert_eq!(tree.get_weight(), 0);
+
+        tree.assign_weights(&FrequencyTable::from_data(b"aaaabbc"));
+        assert_eq!(tree, sample_tree());
+    }
+
+    #[test]
+    fn tree_to_dot() {
+        let tree = FreqTreeNode::composit(FreqTreeNode::leaf(b'"', 1), FreqTreeNode::leaf(b'b', 2));
+        let expected = "\
+digraph huffman {
+    node [fontname=\"monospace\"];
+    n0 [shape=circle, label=\"3\"];
+    n1 [shape=box, label=\"\\\\\\\"\\n1\\n0\"];
+    n0 -> n1 [label=\"0\"];
+    n2 [shape=box, label=\"b\\n2\\n1\"];
+    n0 -> n2 [label=\"1\"];
+}
+";
+        assert_eq!(tree.to_dot(), expected);
+    }
+}
@@ -0,0 +1,458 @@
+//! Literals sections as found at the start of zstd compressed blocks
+//! (RFC 8878 section 3.1.1.3.1), with their Huff0 coded literals.
+//!
+//! A Huff0 table is described by the weights of its symbols, either four
+//! bits each or compressed with FSE. The literals are coded in one stream,
+//! or in four streams behind a jump table of their sizes. Streams are
+//! written back to front: the last literal is written first, starting at the
+//! lowest bit of the first byte, and the stream ends with a 1 bit so that the
+//! decoder can read it from its last byte down to its first.
+
+use crate::canonical::code_lengths;
+use crate::code_book::Code;
+use crate::error::{Error, Result};
+use crate::freq_table::FrequencyTable;
+use crate::fse;
+
+use bitstream::{BackwardBitReader, BackwardBitWriter};
+
+/// The longest code a Huff0 table may have.
+pub const MAX_HUFF0_CODE_LEN: u32 = 11;
+
+/// The largest number of literals a section can hold, the block size limit
+/// of zstd.
+pub const MAX_LITERALS: usize = 128 * 1024;
+
+/// The largest table log for FSE compressed weights.
+const WEIGHTS_MAX_TABLE_LOG: u8 = 6;
+
+/// Sections with fewer literals use a single stream.
+const SINGLE_STREAM_LIMIT: usize = 256;
+
+const RAW_BLOCK: u8 = 0;
+const RLE_BLOCK: u8 = 1;
+/// Compressed sections with a table of their own; treeless sections (3)
+/// reuse the table of the last one.
+const COMPRESSED_BLOCK: u8 = 2;
+
+/// A Huff0 table over byte values. Codes are assigned from the longest to the
+/// shortest, in order of symbol value within a length, starting at all zeros.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Huff0Table {
+    max_bits: u8,
+    /// the code length of every symbol up to the last one with a code
+    lengths: Vec<u8>,
+    codes: Vec<u16>,
+    /// the symbol and code length for every value of max_bits bits
+    decode: Vec<(u8, u8)>,
+}
+
+impl Huff0Table {
+    /// Builds the table for the counts of a FrequencyTable, with codes of
+    /// at most MAX_HUFF0_CODE_LEN bits.
+    pub fn from_frequencies(table: &FrequencyTable) -> Huff0Table {
+        let mut lengths = code_lengths(table.counts(), MAX_HUFF0_CODE_LEN);
+        let last = lengths.iter().rposition(|l| *l > 0).unwrap();
+        lengths.truncate(last + 1);
+        Self::from_lengths(lengths)
+    }
+
+    /// Builds the table from the weights of all symbols but the last one
+    /// with a code, whose weight is implied. Fails with Error::InvalidCode
+    /// if the weights do not describe a complete code of at most
+    /// MAX_HUFF0_CODE_LEN bits.
+    pub fn from_weights(weights: &[u8]) -> Result<Huff0Table> {
+        let invalid = || Error::InvalidCode("invalid huff0 weights");
+        if weights.len() > 255 || weights.iter().any(|w| *w as u32 > MAX_HUFF0_CODE_LEN + 1) {
+            return Err(invalid());
+        }
+
+        let total: u32 = weights.iter().filter(|w| **w > 0).map(|w| 1 << (w - 1)).sum();
+        if total == 0 {
+            return Err(invalid());
+        }
+        let max_bits = fse_highbit(total) + 1;
+        let rest = (1 << max_bits) - total;
+        if max_bits as u32 > MAX_HUFF0_CODE_LEN || !rest.is_power_of_two() {
+            return Err(invalid());
+        }
+
+        let mut all = weights.to_vec();
+        all.push(fse_highbit(rest) + 1);
+        let lengths: Vec<u8> = all.iter().map(|w| if *w > 0 { max_bits + 1 - w } else { 0 }).collect();
+
+        // a complete code has an even number of longest codes, at least two
+        let longest = lengths.iter().filter(|l| **l == max_bits).count();
+        if longest < 2 || longest % 2 != 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self::from_lengths(lengths))
+    }
+
+    fn from_lengths(lengths: Vec<u8>) -> Huff0Table {
+        let max_bits = *lengths.iter().max().unwrap();
+        let mut order: Vec<usize> = (0..lengths.len()).filter(|s| lengths[*s] > 0).collect();
+        order.sort_by_key(|s| std::cmp::Reverse(lengths[*s]));
+
+        let mut codes = vec![0u16; lengths.len()];
+        let mut decode = vec![(0u8, 0u8); 1 << max_bits];
+        let mut next: usize = 0;
+        for s in order {
+            let span = 1 << (max_bits - lengths[s]);
+            codes[s] = (next / span) as u16;
+            for entry in &mut decode[next..next + span] {
+                *entry = (s as u8, lengths[s]);
+            }
+            next += span;
+        }
+
+        Huff0Table { max_bits, lengths, codes, decode }
+    }
+
+    /// Returns the length of the longest code.
+    pub fn max_bits(&self) -> u32 {
+        self.max_bits as u32
+    }
+
+    /// Returns the code of a byte, or None if the byte has no code.
+    pub fn code(&self, byte: u8) -> Option<Code> {
+        match self.lengths.get(byte as usize) {
+            Some(len) if *len > 0 => Some(Code { bits: self.codes[byte as usize] as u64, len: *len }),
+            _ => None,
+        }
+    }
+
+    /// Returns the weights that describe the table: one for every symbol
+    /// but the last one with a code.
+    pub fn weights(&self) -> Vec<u8> {
+        let last = self.lengths.len() - 1;
+        self.lengths[..last].iter().map(|l| if *l > 0 { self.max_bits + 1 - l } else { 0 }).collect()
+    }
+
+    /// Returns the tree description of the table, with the weights FSE
+    /// compressed when that is shorter. Returns None if the table cannot be
+    /// described, which happens when there are more than 128 weights and
+    /// they do not compress.
+    pub fn description(&self) -> Option<Vec<u8>> {
+        let weights = self.weights();
+        let compressed = compress_weights(&weights);
+
+        if weights.len() <= 128 && compressed.as_ref().is_none_or(|c| c.len() >= weights.len().div_ceil(2)) {
+            let mut out = vec![127 + weights.len() as u8];
+            out.extend(weights.chunks(2).map(|pair| (pair[0] << 4) | pair.get(1).unwrap_or(&0)));
+            return Some(out);
+        }
+
+        let compressed = compressed?;
+        let mut out = vec![compressed.len() as u8];
+        out.extend_from_slice(&compressed);
+        Some(out)
+    }
+
+    /// Reads a tree description. Returns the table and the number of bytes
+    /// the description takes.
+    pub fn read_description(buf: &[u8]) -> Result<(Huff0Table, usize)> {
+        let header = *buf.first().ok_or(Error::UnexpectedEof)? as usize;
+        if header >= 128 {
+            let count = header - 127;
+            let len = 1 + count.div_ceil(2);
+            let bytes = buf.get(1..len).ok_or(Error::UnexpectedEof)?;
+            let weights: Vec<u8> = bytes.iter().flat_map(|b| [b >> 4, b & 0x0f]).take(count).collect();
+            return Ok((Self::from_weights(&weights)?, len));
+        }
+
+        let compressed = buf.get(1..1 + header).ok_or(Error::UnexpectedEof)?;
+        let (norm, table_log, used) = fse::read_counts(compressed, MAX_HUFF0_CODE_LEN as usize + 1, WEIGHTS_MAX_TABLE_LOG)?;
+        let weights = fse::decode(&compressed[used..], &norm, table_log, 255)?;
+        Ok((Self::from_weights(&weights)?, 1 + header))
+    }
+
+    /// Codes data as a single stream.
+    ///
+    /// Panics if a byte has no code.
+    pub fn encode_stream(&self, data: &[u8]) -> Vec<u8> {
+        let mut writer = BackwardBitWriter::new();
+        for byte in data.iter().rev() {
+            let code = self.code(*byte).expect("byte has no code");
+            writer.add_bits(code.bits, code.len);
+        }
+        writer.finish()
+    }
+
+    /// Decodes count bytes from a single stream, which must be used up
+    /// exactly.
+    pub fn decode_stream(&self, buf: &[u8], count: usize) -> Result<Vec<u8>> {
+        let mut reader = BackwardBitReader::new(buf).ok_or(Error::InvalidCode("stream has no end marker"))?;
+        let mut out = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (symbol, len) = self.decode[reader.peek_bits(self.max_bits) as usize];
+            reader.skip_bits(len);
+            out.push(symbol);
+        }
+
+        if reader.overflowed() || reader.bits_remaining() != 0 {
+            return Err(Error::InvalidCode("stream size does not match its literals"));
+        }
+        Ok(out)
+    }
+}
+
+/// A decoded literals section.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Literals {
+    /// The literals.
+    pub data: Vec<u8>,
+    /// The size of the section in bytes.
+    pub section_len: usize,
+    /// The Huff0 table that a following treeless section uses: the table of
+    /// this section if it had one, otherwise the previous table.
+    pub table: Option<Huff0Table>,
+}
+
+/// Encodes a literals section for data. Data of a single repeated byte is
+/// stored as RLE, and data that Huff0 coding would not make smaller is stored
+/// raw. Sections of fewer than 256 literals use a single stream.
+///
+/// Panics if data is longer than MAX_LITERALS.
+pub fn encode_literals(data: &[u8]) -> Vec<u8> {
+    assert!(data.len() <= MAX_LITERALS, "{} literals do not fit into a block", data.len());
+
+    if data.len() > 1 && data.iter().all(|b| *b == data[0]) {
+        let mut out = raw_header(RLE_BLOCK, data.len());
+        out.push(data[0]);
+        return out;
+    }
+
+    match encode_compressed(data) {
+        Some(out) if out.len() < data.len() + raw_header(RAW_BLOCK, data.len()).len() => out,
+        _ => {
+            let mut out = raw_header(RAW_BLOCK, data.len());
+            out.extend_from_slice(data);
+            out
+        },
+    }
+}
+
+/// Decodes the literals section at the start of buf. previous is the table
+/// of the last section that had one, which treeless sections use.
+pub fn decode_literals(buf: &[u8], previous: Option<&Huff0Table>) -> Result<Literals> {
+    let b0 = *buf.first().ok_or(Error::UnexpectedEof)?;
+    let block_type = b0 & 3;
+    let size_format = (b0 >> 2) & 3;
+    let byte = |i: usize| buf.get(i).map(|b| *b as usize).ok_or(Error::UnexpectedEof);
+
+    if block_type == RAW_BLOCK || block_type == RLE_BLOCK {
+        let (size, header_len) = match size_format {
+            1 => ((b0 as usize >> 4) | (byte(1)? << 4), 2),
+            3 => ((b0 as usize >> 4) | (byte(1)? << 4) | (byte(2)? << 12), 3),
+            _ => (b0 as usize >> 3, 1),
+        };
+
+        let (data, section_len) = if block_type == RAW_BLOCK {
+            let data = buf.get(header_len..header_len + size).ok_or(Error::UnexpectedEof)?;
+            (data.to_vec(), header_len + size)
+        } else {
+            (vec![byte(header_len)? as u8; size], header_len + 1)
+        };
+        return Ok(Literals { data, section_len, table: previous.cloned() });
+    }
+
+    let (header_len, size_bits) = match size_format {
+        0 | 1 => (3, 10),
+        2 => (4, 14),
+        _ => (5, 18),
+    };
+    let mut header: u64 = 0;
+    for i in 0..header_len {
+        header |= (byte(i)? as u64) << (8 * i);
+    }
+    let mask = (1 << size_bits) - 1;
+    let regenerated = ((header >> 4) & mask) as usize;
+    let compressed = ((header >> (4 + size_bits)) & mask) as usize;
+    let body = buf.get(header_len..header_len + compressed).ok_or(Error::UnexpectedEof)?;
+
+    let (table, streams) = if block_type == COMPRESSED_BLOCK {
+        let (table, len) = Huff0Table::read_description(body)?;
+        (table, &body[len..])
+    } else {
+        let table = previous.ok_or(Error::InvalidHeader("treeless literals without a previous table"))?;
+        (table.clone(), body)
+    };
+
+    let data = if size_format == 0 {
+        table.decode_stream(streams, regenerated)?
+    } else {
+        decode_four_streams(&table, streams, regenerated)?
+    };
+
+    Ok(Literals { data, section_len: header_len + compressed, table: Some(table) })
+}
+
+/// Huff0 codes data, or returns None if it has fewer than two distinct
+/// bytes or the table cannot be described.
+fn encode_compressed(data: &[u8]) -> Option<Vec<u8>> {
+    let freqs = FrequencyTable::from_data(data);
+    if freqs.counts().iter().filter(|c| **c > 0).count() < 2 {
+        return None;
+    }
+
+    let table = Huff0Table::from_frequencies(&freqs);
+    let mut body = table.description()?;
+    let single = data.len() < SINGLE_STREAM_LIMIT;
+    if single {
+        body.extend(table.encode_stream(data));
+    } else {
+        let streams: Vec<Vec<u8>> = data.chunks(data.len().div_ceil(4)).map(|s| table.encode_stream(s)).collect();
+        for stream in &streams[..3] {
+            body.extend_from_slice(&(stream.len() as u16).to_le_bytes());
+        }
+        for stream in &streams {
+            body.extend_from_slice(stream);
+        }
+    }
+
+    let largest = std::cmp::max(data.len(), body.len());
+    let (size_format, header_len, size_bits) = match largest {
+        _ if single => (0, 3, 10),
+        0..=1023 => (1, 3, 10),
+        1024..=16383 => (2, 4, 14),
+        _ => (3, 5, 18),
+    };
+    let header = COMPRESSED_BLOCK as u64 | (size_format << 2) | ((data.len() as u64) << 4)
+        | ((body.len() as u64) << (4 + size_bits));
+
+    let mut out = header.to_le_bytes()[..header_len].to_vec();
+    out.extend(body);
+    Some(out)
+}
+
+/// The header of a raw or RLE section.
+fn raw_header(block_type: u8, size: usize) -> Vec<u8> {
+    match size {
+        0..=31 => vec![block_type | (size << 3) as u8],
+        32..=4095 => (block_type as u16 | 1 << 2 | (size << 4) as u16).to_le_bytes().to_vec(),
+        _ => (block_type as u32 | 3 << 2 | (size << 4) as u32).to_le_bytes()[..3].to_vec(),
+    }
+}
+
+fn decode_four_streams(table: &Huff0Table, buf: &[u8], regenerated: usize) -> Result<Vec<u8>> {
+    if buf.len() < 6 {
+        return Err(Error::UnexpectedEof);
+    }
+    let sizes: Vec<usize> = buf[..6].chunks(2).map(|s| u16::from_le_bytes([s[0], s[1]]) as usize).collect();
+    let segment = regenerated.div_ceil(4);
+    if segment * 3 > regenerated {
+        return Err(Error::InvalidHeader("too few literals for four streams"));
+    }
+
+    let mut out = Vec::with_capacity(regenerated);
+    let mut rest = &buf[6..];
+    for size in sizes {
+        if size > rest.len() {
+            return Err(Error::UnexpectedEof);
+        }
+        out.extend(table.decode_stream(&rest[..size], segment)?);
+        rest = &rest[size..];
+    }
+    out.extend(table.decode_stream(rest, regenerated - 3 * segment)?);
+    Ok(out)
+}
+
+/// FSE compresses weights, or returns None if they do not compress into a
+/// description of less than 128 bytes.
+fn compress_weights(weights: &[u8]) -> Option<Vec<u8>> {
+    let mut counts = vec![0u32; *weights.iter().max()? as usize + 1];
+    for w in weights {
+        counts[*w as usize] += 1;
+    }
+    if weights.len() < 2 || counts.iter().any(|c| *c as usize == weights.len()) {
+        return None;
+    }
+
+    let norm = fse::normalize(&counts, WEIGHTS_MAX_TABLE_LOG)?;
+    let mut out = Vec::new();
+    fse::write_counts(&norm, WEIGHTS_MAX_TABLE_LOG, &mut out);
+    out.extend(fse::encode(weights, &norm, WEIGHTS_MAX_TABLE_LOG));
+    if out.len() >= 128 {
+        return None;
+    }
+    Some(out)
+}
+
+fn fse_highbit(val: u32) -> u8 {
+    31 - val.leading_zeros() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_zstd_section() {
+        // the literals of a block that zstd -19 compressed, with FSE
+        // compressed weights
+        let section = [
+            0xa2, 0xc4, 0x11, 0x18, 0x60, 0xaf, 0x0e, 0x9f, 0xbc, 0xb4, 0xc7, 0x05, 0x12, 0x98, 0x90, 0x0a,
+            0x82, 0x60, 0x5c, 0xca, 0x6d, 0x94, 0xd5, 0x0d, 0x24, 0x41, 0x10, 0xf0, 0xcd, 0x58, 0xef, 0xc6,
+            0xd6, 0x1e, 0x33, 0x3b, 0xb7, 0xaf, 0x1f, 0x8c, 0x30, 0xbe, 0x28, 0x59, 0xf6, 0xf6, 0x01, 0x83,
+            0x12, 0xb7, 0xae, 0x60, 0x79, 0x9d, 0x13, 0x3d, 0xe4, 0x30, 0x52, 0x72, 0xf6, 0x8a, 0x16, 0x6f,
+            0x28, 0x5a, 0x85, 0x2b, 0x03, 0x22, 0x3d, 0x9f, 0x47, 0x14,
+        ];
+        let literals = decode_literals(&section, None).unwrap();
+        assert_eq!(literals.data, &b"the quick brown fox 0 jumps over lazy dog; 9198387576765951443335227119009"[..]);
+        assert_eq!(literals.section_len, section.len());
+
+        // a treeless section needs the table of an earlier one
+        let treeless = [0x03 | (2 << 4), 0x00, 0x00];
+        assert!(matches!(decode_literals(&treeless, None), Err(Error::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_encode_section() {
+        // zstd decodes this section to the same literals
+        let data = b"abracadabra, abracadabra!";
+        let section = encode_literals(data);
+        assert_eq!(section, vec![0x92, 0x41, 0x05, 0x0b, 0xc1, 0x6b, 0xc6, 0x9d, 0x50, 0x3f, 0xd5, 0xdd,
+            0x7e, 0xae, 0x2b, 0xe1, 0x74, 0xca, 0x29, 0x88, 0xd3, 0x29, 0xa7, 0x01]);
+        assert_eq!(decode_literals(&section, None).unwrap().data, data);
+
+        assert_eq!(encode_literals(b""), vec![0x00]);
+        assert_eq!(encode_literals(b"zzzzzzzzzz"), vec![0x51, b'z']);
+        assert_eq!(encode_literals(b"xy"), vec![0x10, b'x', b'y']);
+    }
+
+    #[test]
+    fn test_section_roundtrip() {
+        let mut x: u32 = 7;
+        for len in &[255, 256, 1500, 20000, MAX_LITERALS] {
+            let data: Vec<u8> = (0..*len).map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                let r = (x >> 16) % 1000;
+                (r * r / 10000) as u8
+            }).collect();
+
+            let section = encode_literals(&data);
+            assert_eq!(section[0] & 3, COMPRESSED_BLOCK);
+            let literals = decode_literals(&section, None).unwrap();
+            assert_eq!((literals.data, literals.section_len), (data, section.len()));
+        }
+    }
+
+    #[test]
+    fn test_direct_weights() {
+        let table = Huff0Table::from_frequencies(&FrequencyTable::from_data(&[0, 0, 0, 0, 1, 1, 2]));
+        assert_eq!(table.code(0).unwrap().to_string(), "1");
+        assert_eq!(table.code(1).unwrap().to_string(), "00");
+        assert_eq!(table.code(2).unwrap().to_string(), "01");
+        assert!(table.code(3).is_none());
+        assert_eq!(table.weights(), vec![2, 1]);
+
+        let description = table.description().unwrap();
+        assert_eq!(description, vec![129, 0x21]);
+        assert_eq!(Huff0Table::read_description(&description).unwrap(), (table, description.len()));
+
+        assert!(matches!(Huff0Table::from_weights(&[3, 1]), Err(Error::InvalidCode(_))));
+        assert!(matches!(Huff0Table::from_weights(&[0, 0]), Err(Error::InvalidCode(_))));
+    }
+}